@@ -0,0 +1,227 @@
+use std::cmp::Reverse;
+
+use chrono::Utc;
+use rusqlite::{Connection, params};
+use serenity::client::Context;
+use serenity::model::channel::{ChannelType, PermissionOverwrite, PermissionOverwriteType};
+use serenity::model::id::{ChannelId, GuildId, RoleId, UserId};
+use serenity::model::Permissions;
+
+use crate::bot::config::{Assignable, GuildConfig};
+use crate::bot::db::{audit, db};
+use crate::bot::mc::utils::user_join_chan;
+use crate::bot::metrics;
+
+/// Turn a requested name into the channel name Discord would make of it, so the same game asked
+/// for twice with different capitals or spacing counts once.
+pub fn normalize(name: &str) -> String {
+	name.split_whitespace()
+		.collect::<Vec<_>>()
+		.join("-")
+		.to_lowercase()
+		.chars()
+		.filter(|x| x.is_alphanumeric() || *x == '-' || *x == '_')
+		.collect()
+}
+
+/// Every open request in a group, as (name, requesters), most requested first.
+pub fn open(db: &Connection, guild: GuildId, group: &str) -> Vec<(String, Vec<UserId>)> {
+	let mut stmt = db.prepare("SELECT name, user_id FROM channel_requests WHERE guild_id = ?1 AND group_id = ?2 ORDER BY requested_at").unwrap();
+	let rows = stmt.query_map(params![guild.0, group], |r| Ok((r.get::<_, String>(0)?, UserId(r.get(1)?)))).unwrap();
+
+	let mut open: Vec<(String, Vec<UserId>)> = vec![];
+	for (name, user) in rows.filter_map(|x| x.ok()) {
+		match open.iter_mut().find(|(x, _)| *x == name) {
+			Some((_, users)) => users.push(user),
+			None => open.push((name, vec![user])),
+		}
+	}
+
+	open.sort_by_key(|(_, users)| Reverse(users.len()));
+	open
+}
+
+/// A claim on creating a channel older than this is from a run that never finished, like one cut
+/// off by a restart, so it no longer blocks anyone.
+const CLAIM_TIMEOUT: i64 = 10 * 60;
+
+/// What recording a request led to.
+pub enum Requested {
+	/// Still short of the threshold; holds how many people have asked.
+	Waiting(usize),
+	/// This request reached the threshold, and the caller now has to create the channel for these
+	/// requesters.
+	Claimed(Vec<UserId>),
+	/// The threshold was already reached, and someone else is creating the channel.
+	Creating,
+}
+
+/// Record a user's request for a channel; asking twice changes nothing. Counting the requests and
+/// claiming the creation happen in one transaction, so only one request ever creates the channel.
+pub fn request(db: &Connection, guild: GuildId, group: &str, name: &str, user: UserId, threshold: usize) -> rusqlite::Result<Requested> {
+	let now = Utc::now().timestamp();
+	let tx = db.unchecked_transaction()?;
+
+	tx.execute(
+		"INSERT OR IGNORE INTO channel_requests (guild_id, group_id, name, user_id, requested_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+		params![guild.0, group, name, user.0, now],
+	)?;
+
+	let users = requesters(&tx, guild, group, name);
+	if users.len() < threshold {
+		tx.commit()?;
+		return Ok(Requested::Waiting(users.len()));
+	}
+
+	tx.execute(
+		"DELETE FROM channel_request_claims WHERE guild_id = ?1 AND group_id = ?2 AND name = ?3 AND claimed_at < ?4",
+		params![guild.0, group, name, now - CLAIM_TIMEOUT],
+	)?;
+	let claimed = tx.execute(
+		"INSERT OR IGNORE INTO channel_request_claims (guild_id, group_id, name, claimed_at) VALUES (?1, ?2, ?3, ?4)",
+		params![guild.0, group, name, now],
+	)? == 1;
+
+	tx.commit()?;
+	Ok(if claimed { Requested::Claimed(users) } else { Requested::Creating })
+}
+
+/// Give up a claim on creating a channel, so the next request tries again.
+fn release(db: &Connection, guild: GuildId, group: &str, name: &str) {
+	if db.execute("DELETE FROM channel_request_claims WHERE guild_id = ?1 AND group_id = ?2 AND name = ?3", params![guild.0, group, name]).is_err() {
+		error!("Error releasing the claim on creating channel {}", name);
+	}
+}
+
+pub fn requesters(db: &Connection, guild: GuildId, group: &str, name: &str) -> Vec<UserId> {
+	let mut stmt = db.prepare("SELECT user_id FROM channel_requests WHERE guild_id = ?1 AND group_id = ?2 AND name = ?3").unwrap();
+	let rows = stmt.query_map(params![guild.0, group, name], |r| r.get(0)).unwrap();
+	rows.filter_map(|x| x.ok()).map(UserId).collect()
+}
+
+/// Create a requested channel under the group's first category, hidden from everyone but the
+/// people who asked for it, and close the request. Only call this holding the claim from
+/// `Requested::Claimed`; it's released if creating fails.
+pub async fn create(ctx: &Context, guild: &GuildConfig, group: &Assignable, name: &str, requesters: &[UserId]) -> Option<ChannelId> {
+	let category = match guild.categories(group).first() {
+		Some(category) => *category,
+		None => {
+			error!("Group {} has no category to create requested channel {} in", group.id, name);
+			release(&db(ctx).await.lock().unwrap(), guild.id, group.id, name);
+			return None;
+		}
+	};
+
+	let overwrites = vec![
+		// @everyone shares the guild's ID.
+		PermissionOverwrite { allow: Permissions::empty(), deny: Permissions::VIEW_CHANNEL, kind: PermissionOverwriteType::Role(RoleId(guild.id.0)) },
+		PermissionOverwrite { allow: Permissions::VIEW_CHANNEL, deny: Permissions::empty(), kind: PermissionOverwriteType::Member(ctx.cache.current_user_id()) },
+	];
+
+	let channel = guild.id.create_channel(&ctx.http, |c| c.name(name).kind(ChannelType::Text).category(category).permissions(overwrites)).await;
+	let channel = match channel {
+		Ok(channel) => channel.id,
+		Err(_) => {
+			error!("Error creating requested channel {}", name);
+			metrics::discord_error("create_channel");
+			release(&db(ctx).await.lock().unwrap(), guild.id, group.id, name);
+			return None;
+		}
+	};
+
+	info!("Created requested channel {} for {} members", name, requesters.len());
+
+	{
+		let db = db(ctx).await;
+		let db = db.lock().unwrap();
+
+		if db.execute("DELETE FROM channel_requests WHERE guild_id = ?1 AND group_id = ?2 AND name = ?3", params![guild.id.0, group.id, name]).is_err() {
+			error!("Error closing the request for channel {}", name);
+		}
+		release(&db, guild.id, group.id, name);
+		audit(&db, "channel-requests", None, "channel-request-create", &format!("{}: {}", group.id, name));
+	}
+
+	for user in requesters {
+		match user.to_user(ctx).await {
+			Ok(user) => user_join_chan(ctx, &user, channel).await,
+			Err(_) => {
+				error!("Error retrieving requester {} of channel {}", user, name);
+				metrics::discord_error("get_user");
+			}
+		}
+	}
+
+	Some(channel)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::bot::db::open_memory;
+
+	const GUILD: GuildId = GuildId(1);
+
+	fn ask(db: &Connection, name: &str, user: u64) -> Requested {
+		request(db, GUILD, "games", name, UserId(user), 3).unwrap()
+	}
+
+	#[test]
+	fn normalize_names() {
+		assert_eq!(normalize("  Kerbal Space   Program! "), "kerbal-space-program");
+		assert_eq!(normalize("factorio_2"), "factorio_2");
+	}
+
+	#[test]
+	fn counts_each_requester_once() {
+		let db = open_memory();
+
+		assert!(matches!(ask(&db, "factorio", 1), Requested::Waiting(1)));
+		assert!(matches!(ask(&db, "factorio", 1), Requested::Waiting(1)));
+		assert!(matches!(ask(&db, "factorio", 2), Requested::Waiting(2)));
+
+		// Requests for other channels don't count toward this one.
+		assert!(matches!(ask(&db, "minecraft", 3), Requested::Waiting(1)));
+	}
+
+	#[test]
+	fn only_one_request_claims_creation() {
+		let db = open_memory();
+
+		ask(&db, "factorio", 1);
+		ask(&db, "factorio", 2);
+		match ask(&db, "factorio", 3) {
+			Requested::Claimed(users) => assert_eq!(users.len(), 3),
+			_ => panic!("the third request should claim creating the channel"),
+		}
+		assert!(matches!(ask(&db, "factorio", 4), Requested::Creating));
+
+		// Giving the claim up lets the next request try again.
+		release(&db, GUILD, "games", "factorio");
+		assert!(matches!(ask(&db, "factorio", 5), Requested::Claimed(_)));
+	}
+
+	#[test]
+	fn stale_claims_expire() {
+		let db = open_memory();
+
+		ask(&db, "factorio", 1);
+		ask(&db, "factorio", 2);
+		ask(&db, "factorio", 3);
+		db.execute("UPDATE channel_request_claims SET claimed_at = claimed_at - ?1", [CLAIM_TIMEOUT + 1]).unwrap();
+
+		assert!(matches!(ask(&db, "factorio", 4), Requested::Claimed(_)));
+	}
+
+	#[test]
+	fn open_requests_most_wanted_first() {
+		let db = open_memory();
+
+		ask(&db, "minecraft", 1);
+		ask(&db, "factorio", 1);
+		ask(&db, "factorio", 2);
+
+		let open: Vec<_> = open(&db, GUILD, "games").into_iter().map(|(name, users)| (name, users.len())).collect();
+		assert_eq!(open, vec![("factorio".to_string(), 2), ("minecraft".to_string(), 1)]);
+	}
+}
\ No newline at end of file
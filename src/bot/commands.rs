@@ -1,27 +1,108 @@
+use std::time::Duration;
+
+use chrono::Utc;
+
 use serenity::client::Context;
-use serenity::model::channel::ChannelType;
-use serenity::model::interactions::application_command::ApplicationCommandInteraction;
-use serenity::model::interactions::{InteractionApplicationCommandCallbackDataFlags, InteractionResponseType};
+use serenity::model::application::component::ButtonStyle;
+use serenity::model::application::interaction::application_command::{ApplicationCommandInteraction, CommandDataOption, CommandDataOptionValue};
+use serenity::model::application::interaction::{InteractionResponseType, MessageFlags};
+use serenity::model::channel::AttachmentType;
 use serenity::model::prelude::ChannelId;
 
 use crate::bot::Bot;
-use crate::bot::config::{ALLOWED_MEMBERSHIPS, CAT_CHANNELS, GUILD_ID, MEMBERSHIP_ALUMNI, MEMBERSHIP_FRIEND, MEMBERSHIP_MEMBER};
+use crate::bot::config::{ChannelAccess, GuildConfig, RoleList, guild_config};
+use crate::bot::db::{audit, db};
 use crate::bot::mc::MC;
-use crate::bot::mc::utils::{user_change_role, user_join_chan, user_leave_chan};
+use crate::bot::mc::utils::{SLOW_DOWN, chan_access, chan_joinable, migrate_chan_access, rate_limit, user_add_role, user_change_role, user_join_chan, user_leave_chan, user_remove_role};
+use crate::bot::metrics;
+use crate::bot::permissions;
+use crate::bot::projects;
+use crate::bot::reconcile::{find_drift, update_records};
+use crate::bot::rollover;
+use crate::bot::roster::{apply_import, build_export, current_term, export_csv, export_json, fetch_members, parse_roster, plan_import};
+use crate::bot::scheduler::{discord_time, list_jobs, schedule_once};
+use crate::bot::stats;
+
+/// How many members `/project roster` lists.
+const ROSTER_MAX: usize = 100;
 
 impl Bot {
 	pub async fn handle_command(&self, ctx: Context, command: ApplicationCommandInteraction) {
 		trace!("Handling command {} from {}", command.data.name, command.user.tag());
+		metrics::COMMANDS.with_label_values(&[command.data.name.as_str()]).inc();
+		let timer = metrics::LATENCY.with_label_values(&["command"]).start_timer();
+
+		let guild = match command.guild_id.and_then(guild_config) {
+			Some(guild) => guild,
+			None => {
+				warn!("{} called /{} outside a configured guild", command.user.tag(), command.data.name);
+				timer.stop_and_discard();
+				command.create_interaction_response(&ctx.http, |r| {
+					r.kind(InteractionResponseType::ChannelMessageWithSource);
+					r.interaction_response_data(|d| {
+						d.flags(MessageFlags::EPHEMERAL);
+						d.content("Mission Control isn't set up for this server.")
+					})
+				}).await.unwrap();
+				return;
+			}
+		};
+
+		if !permissions::allowed(guild, &command.data.name, command.member.as_ref()) {
+			permissions::denied(guild, &command.data.name, command.member.as_ref());
+			timer.stop_and_discard();
+			command.create_interaction_response(&ctx.http, |r| {
+				r.kind(InteractionResponseType::ChannelMessageWithSource);
+				r.interaction_response_data(|d| {
+					d.flags(MessageFlags::EPHEMERAL);
+					d.content(format!("You don't have permission to use /{}.", command.data.name))
+				})
+			}).await.unwrap();
+			return;
+		}
+
 		match command.data.name.as_str() {
-			"mc" => MC::from_command(ctx, command).await,
-			"become" => Bot::handle_become(ctx, command).await,
-			"join" => Bot::handle_join(ctx, command).await,
-			"leave" => Bot::handle_leave(ctx, command).await,
+			// MC times its own responses, since the session outlives this call.
+			"mc" => {
+				timer.stop_and_discard();
+				MC::from_command(ctx, command, guild, &self.sessions, false).await;
+				return;
+			}
+			"mcadmin" => {
+				timer.stop_and_discard();
+				MC::from_command(ctx, command, guild, &self.sessions, true).await;
+				return;
+			}
+			"become" => Bot::handle_become(ctx, command, guild).await,
+			"join" => Bot::handle_join(ctx, command, guild).await,
+			"leave" => Bot::handle_leave(ctx, command, guild).await,
+			"project" => Bot::handle_project(ctx, command, guild).await,
+			"project-admin" => Bot::handle_project_admin(ctx, command, guild).await,
+			"migrate-access" => Bot::handle_migrate_access(ctx, command, guild).await,
+			"reconcile" => Bot::handle_reconcile(ctx, command, guild).await,
+			"import" => Bot::handle_import(ctx, command, guild).await,
+			"export" => Bot::handle_export(ctx, command, guild).await,
+			"stats" => Bot::handle_stats(ctx, command, guild).await,
+			"jobs" => self.handle_jobs(ctx, command, guild).await,
+			"rollover" => Bot::handle_rollover(ctx, command, guild).await,
 			_ => error!("Received an unimplemented command {}!", command.data.name.as_str()),
 		};
+
+		timer.observe_duration();
+	}
+
+	/// Tell someone their change was rate limited.
+	async fn slow_down(ctx: &Context, command: &ApplicationCommandInteraction) {
+		command.create_interaction_response(&ctx.http, |r| {
+			r.kind(InteractionResponseType::ChannelMessageWithSource);
+			r.interaction_response_data(|d| {
+				d.flags(MessageFlags::EPHEMERAL);
+				d.content(SLOW_DOWN)
+			})
+		}).await.unwrap();
 	}
 
-	async fn handle_become(ctx: Context, command: ApplicationCommandInteraction) {
+	async fn handle_become(ctx: Context, command: ApplicationCommandInteraction, guild: &'static GuildConfig) {
 		let opt = command.data.options.first();
 		if opt.is_none() {
 			error!("Somehow called /become with no option!");
@@ -33,15 +114,20 @@ impl Bot {
 
 		debug!("{} called /become with: {}", command.user.tag(), choice);
 
+		if rate_limit(&ctx, guild.id, &command.user).await.is_err() {
+			Bot::slow_down(&ctx, &command).await;
+			return;
+		}
+
 		match choice {
 			"member" => {
-				user_change_role(&ctx, &command.user, MEMBERSHIP_MEMBER, ALLOWED_MEMBERSHIPS).await;
+				user_change_role(&ctx, guild.id, &command.user, guild.member, &guild.role_list(RoleList::Memberships)).await;
 			}
 			"alumni" => {
-				user_change_role(&ctx, &command.user, MEMBERSHIP_ALUMNI, ALLOWED_MEMBERSHIPS).await;
+				user_change_role(&ctx, guild.id, &command.user, guild.alumni, &guild.role_list(RoleList::Memberships)).await;
 			}
 			"friend" => {
-				user_change_role(&ctx, &command.user, MEMBERSHIP_FRIEND, ALLOWED_MEMBERSHIPS).await;
+				user_change_role(&ctx, guild.id, &command.user, guild.friend, &guild.role_list(RoleList::Memberships)).await;
 			}
 			_ => {
 				error!("Somehow sent an invalid choice for /become: {}", choice);
@@ -49,7 +135,7 @@ impl Bot {
 		}
 	}
 
-	async fn handle_join(ctx: Context, command: ApplicationCommandInteraction) {
+	async fn handle_join(ctx: Context, command: ApplicationCommandInteraction, guild: &'static GuildConfig) {
 		let opt = command.data.options.first();
 		if opt.is_none() {
 			error!("Somehow called /join with no option!");
@@ -63,18 +149,10 @@ impl Bot {
 
 		debug!("{} called /join with: {}", command.user.tag(), choice);
 
-		let chans = GUILD_ID.channels(&ctx).await.unwrap();
+		let chans = guild.id.channels(&ctx).await.unwrap();
 		let chans: Vec<_>= chans
 			.values()
-			.filter(|x| {
-				if let Some(pid) = x.parent_id {
-					if pid == CAT_CHANNELS && x.kind == ChannelType::Text {
-						return true;
-					}
-				}
-
-				false
-			})
+			.filter(|x| chan_joinable(x))
 			.collect();
 
 		let selected_chan = chans.iter().find(|c| c.name == channel);
@@ -84,18 +162,23 @@ impl Bot {
 				command.create_interaction_response(&ctx.http, |r| {
 					r.kind(InteractionResponseType::ChannelMessageWithSource);
 					r.interaction_response_data(|d| {
-						d.flags(InteractionApplicationCommandCallbackDataFlags::EPHEMERAL);
+						d.flags(MessageFlags::EPHEMERAL);
 						d.content(format!("Error: \"{}\" is not a joinable channel!", channel))
 					})
 				}).await.unwrap();
 			}
 			Some(chan) => {
+				if rate_limit(&ctx, guild.id, &command.user).await.is_err() {
+					Bot::slow_down(&ctx, &command).await;
+					return;
+				}
+
 				user_join_chan(&ctx, &command.user, chan.id).await;
 
 				command.create_interaction_response(&ctx.http, |r| {
 					r.kind(InteractionResponseType::ChannelMessageWithSource);
 					r.interaction_response_data(|d| {
-						d.flags(InteractionApplicationCommandCallbackDataFlags::EPHEMERAL);
+						d.flags(MessageFlags::EPHEMERAL);
 						d.content(format!("You've successfully joined <#{}>!", chan.id.0))
 					})
 				}).await.unwrap();
@@ -103,7 +186,7 @@ impl Bot {
 		}
 	}
 
-	async fn handle_leave(ctx: Context, command: ApplicationCommandInteraction) {
+	async fn handle_leave(ctx: Context, command: ApplicationCommandInteraction, guild: &'static GuildConfig) {
 		let opt = command.data.options.first();
 		if opt.is_none() {
 			error!("Somehow called /leave with no option!");
@@ -116,18 +199,10 @@ impl Bot {
 
 		debug!("{} called /leave with: {}", command.user.tag(), choice);
 
-		let chans = GUILD_ID.channels(&ctx).await.unwrap();
+		let chans = guild.id.channels(&ctx).await.unwrap();
 		let chans: Vec<_>= chans
 			.values()
-			.filter(|x| {
-				if let Some(pid) = x.parent_id {
-					if pid == CAT_CHANNELS && x.kind == ChannelType::Text {
-						return true;
-					}
-				}
-
-				false
-			})
+			.filter(|x| chan_joinable(x))
 			.collect();
 
 		let selected_chan = chans.iter().find(|c| c.id == channel);
@@ -137,22 +212,780 @@ impl Bot {
 				command.create_interaction_response(&ctx.http, |r| {
 					r.kind(InteractionResponseType::ChannelMessageWithSource);
 					r.interaction_response_data(|d| {
-						d.flags(InteractionApplicationCommandCallbackDataFlags::EPHEMERAL);
+						d.flags(MessageFlags::EPHEMERAL);
 						d.content(format!("Error: <#{}> is not a leavable channel!", channel))
 					})
 				}).await.unwrap();
 			}
 			Some(chan) => {
-				user_leave_chan(&ctx, &command.user, chan.id).await;
+				if rate_limit(&ctx, guild.id, &command.user).await.is_err() {
+					Bot::slow_down(&ctx, &command).await;
+					return;
+				}
+
+				let content = match user_leave_chan(&ctx, &command.user, chan.id).await {
+					Ok(_) => format!("You've successfully left #{}!", chan.name),
+					Err(why) => why.message().to_string(),
+				};
+
+				command.create_interaction_response(&ctx.http, |r| {
+					r.kind(InteractionResponseType::ChannelMessageWithSource);
+					r.interaction_response_data(|d| {
+						d.flags(MessageFlags::EPHEMERAL);
+						d.content(content)
+					})
+				}).await.unwrap();
+			}
+		}
+	}
+
+	async fn handle_project(ctx: Context, command: ApplicationCommandInteraction, guild: &'static GuildConfig) {
+		let sub = match command.data.options.first() {
+			Some(sub) => sub,
+			None => {
+				error!("Somehow called /project with no subcommand!");
+				return;
+			}
+		};
+		let sub_option = |name: &str| sub.options.iter().find(|x| x.name == name);
+		let sub_string = |name: &str| sub_option(name).and_then(|x| x.value.as_ref()).and_then(|x| x.as_str()).map(|x| x.trim().to_string());
+		let remove = sub_option("remove").and_then(|x| x.value.as_ref()).and_then(|x| x.as_bool()).unwrap_or(false);
+
+		debug!("{} called /project {}", command.user.tag(), sub.name);
+
+		let project = match sub_option("project").and_then(|x| x.resolved.as_ref()) {
+			Some(CommandDataOptionValue::Role(role)) => role.clone(),
+			_ => {
+				error!("Somehow called /project {} with no project!", sub.name);
+				return;
+			}
+		};
+
+		if !guild.role_list(RoleList::Projects).contains(&project.id) {
+			reply(&ctx, &command, format!("Error: <@&{}> is not a project!", project.id)).await;
+			return;
+		}
+
+		let db = db(&ctx).await;
+
+		if sub.name == "info" {
+			let profile = projects::profile(&db.lock().unwrap(), project.id);
+
+			command.create_interaction_response(&ctx.http, |r| {
+				r.kind(InteractionResponseType::ChannelMessageWithSource);
+				r.interaction_response_data(|d| {
+					d.flags(MessageFlags::EPHEMERAL);
+					d.embed(|e| {
+						e
+							.title(&project.name)
+							.colour(project.colour)
+							.description(profile.description.as_deref().unwrap_or("No description yet."))
+							.field("Leads", list_or_none(profile.leads.iter().map(|x| format!("<@{}>", x))), true)
+							.field("Meets", profile.meeting.as_deref().unwrap_or("Not set"), true)
+							.field("Channels", list_or_none(profile.channels.iter().map(|x| format!("<#{}>", x))), false)
+							.field("Links", list_or_none(profile.links.iter().cloned()), false)
+					})
+				})
+			}).await.unwrap();
+			return;
+		}
+
+		// Leads can edit their own project; only officers can pick its leads.
+		let officer = permissions::is_officer(guild, command.member.as_ref());
+		let lead = projects::is_lead(&db.lock().unwrap(), project.id, command.user.id);
+		if !officer && (sub.name == "lead" || !lead) {
+			permissions::denied(guild, "project", command.member.as_ref());
+			let content = if sub.name == "lead" {
+				"Only officers can change a project's leads."
+			} else {
+				"Only this project's leads and officers can do that."
+			};
+			reply(&ctx, &command, content).await;
+			return;
+		}
+
+		match sub.name.as_str() {
+			"roster" => {
+				command.defer_ephemeral(&ctx.http).await.unwrap();
+
+				let leads = projects::profile(&db.lock().unwrap(), project.id).leads;
+				let content = match fetch_members(&ctx.http, guild.id).await {
+					Ok(members) => {
+						let names: Vec<_> = members.iter()
+							.filter(|x| x.roles.contains(&project.id))
+							.map(|x| if leads.contains(&x.user.id) { format!("<@{}> (lead)", x.user.id) } else { format!("<@{}>", x.user.id) })
+							.collect();
+
+						let mut out = format!("**{}** has {} members.\n", project.name, names.len());
+						for name in names.iter().take(ROSTER_MAX) {
+							out.push_str(&format!("- {}\n", name));
+						}
+						if names.len() > ROSTER_MAX {
+							out.push_str(&format!("...and {} more\n", names.len() - ROSTER_MAX));
+						}
+						out
+					}
+					Err(_) => {
+						error!("Error retrieving members for the {} roster", project.name);
+						"Error: couldn't retrieve the roster, try again in a minute.".to_string()
+					}
+				};
+
+				command.edit_original_interaction_response(&ctx.http, |r| {
+					r.embed(|e| e.description(content))
+				}).await.unwrap();
+				return;
+			}
+			"add-member" | "remove-member" => {
+				let user = match sub_option("user").and_then(|x| x.resolved.as_ref()) {
+					Some(CommandDataOptionValue::User(user, _)) => user.clone(),
+					_ => {
+						error!("Somehow called /project {} with no user!", sub.name);
+						return;
+					}
+				};
+				let add = sub.name == "add-member";
+
+				// Leads can't hand out a project to people who've left the server.
+				let member = match guild.id.member(&ctx, user.id).await {
+					Ok(member) => member,
+					Err(_) => {
+						reply(&ctx, &command, format!("Error: <@{}> isn't in this server!", user.id)).await;
+						return;
+					}
+				};
+
+				if member.roles.contains(&project.id) == add {
+					let content = if add {
+						format!("<@{}> is already a member of {}.", user.id, project.name)
+					} else {
+						format!("<@{}> isn't a member of {}.", user.id, project.name)
+					};
+					reply(&ctx, &command, content).await;
+					return;
+				}
+
+				info!("{} is {} {} {} project {}", command.user.tag(), if add { "adding" } else { "removing" }, user.tag(), if add { "to" } else { "from" }, project.name);
+				let done = if add {
+					user_add_role(&ctx, guild.id, &user, project.id).await
+				} else {
+					user_remove_role(&ctx, guild.id, &user, project.id).await
+				};
+
+				if !done {
+					let content = if add {
+						format!("Error: couldn't add <@{}> to {}, try again in a minute.", user.id, project.name)
+					} else {
+						format!("Error: couldn't remove <@{}> from {}, try again in a minute.", user.id, project.name)
+					};
+					reply(&ctx, &command, content).await;
+					return;
+				}
+
+				let action = if add { "project-add-member" } else { "project-remove-member" };
+				audit(&db.lock().unwrap(), &command.user.tag(), Some(user.id), action, &project.name);
+
+				let content = if add {
+					format!("Added <@{}> to {}.", user.id, project.name)
+				} else {
+					format!("Removed <@{}> from {}.", user.id, project.name)
+				};
+				reply(&ctx, &command, content).await;
+				return;
+			}
+			_ => {}
+		}
+
+		// Linked channels are joined along with the project, so they have to be joinable anyway.
+		let channel = match sub_option("channel").and_then(|x| x.resolved.as_ref()) {
+			Some(CommandDataOptionValue::Channel(channel)) => {
+				let chans = guild.id.channels(&ctx).await.unwrap();
+				match chans.get(&channel.id) {
+					Some(chan) if remove || chan_joinable(chan) => Some(chan.id),
+					_ => {
+						reply(&ctx, &command, format!("Error: <#{}> is not a joinable channel!", channel.id)).await;
+						return;
+					}
+				}
+			}
+			_ => None,
+		};
+
+		let content = {
+			let db = db.lock().unwrap();
+			let actor = command.user.tag();
+
+			match sub.name.as_str() {
+				"edit" => {
+					let mut profile = projects::profile(&db, project.id);
+					let cleared = |x: String| if x.eq_ignore_ascii_case("none") { None } else { Some(x) };
+
+					if let Some(description) = sub_string("description") {
+						profile.description = cleared(description);
+					}
+					if let Some(meeting) = sub_string("meeting") {
+						profile.meeting = cleared(meeting);
+					}
+					if let Some(links) = sub_string("links") {
+						profile.links = match cleared(links) {
+							Some(links) => links.split_whitespace().map(|x| x.to_string()).collect(),
+							None => vec![],
+						};
+					}
+
+					match profile.links.iter().find(|x| !x.starts_with("https://") && !x.starts_with("http://")) {
+						Some(link) => format!("Error: {} isn't a link!", link),
+						None if projects::save_details(&db, project.id, &profile) => {
+							audit(&db, &actor, None, "project-edit", &project.name);
+							format!("Updated {}.", project.name)
+						}
+						None => {
+							error!("Error saving profile for project {}", project.name);
+							"Error: couldn't save that, try again in a minute.".to_string()
+						}
+					}
+				}
+				"channel" => {
+					let channel = channel.unwrap();
+					if projects::set_channel(&db, project.id, channel, !remove) {
+						let action = if remove { "project-unlink" } else { "project-link" };
+						audit(&db, &actor, None, action, &format!("{}: {}", project.name, channel));
+						if remove {
+							format!("Unlinked <#{}> from {}.", channel, project.name)
+						} else {
+							format!("Linked <#{}> to {}.", channel, project.name)
+						}
+					} else {
+						error!("Error linking channel {} to project {}", channel, project.name);
+						"Error: couldn't save that, try again in a minute.".to_string()
+					}
+				}
+				"lead" => {
+					let user = match sub_option("user").and_then(|x| x.resolved.as_ref()) {
+						Some(CommandDataOptionValue::User(user, _)) => user.id,
+						_ => {
+							error!("Somehow called /project lead with no user!");
+							return;
+						}
+					};
+
+					if projects::set_lead(&db, project.id, user, !remove) {
+						let action = if remove { "project-lead-remove" } else { "project-lead-add" };
+						audit(&db, &actor, Some(user), action, &project.name);
+						if remove {
+							format!("<@{}> is no longer a lead of {}.", user, project.name)
+						} else {
+							format!("<@{}> is now a lead of {}.", user, project.name)
+						}
+					} else {
+						error!("Error changing leads of project {}", project.name);
+						"Error: couldn't save that, try again in a minute.".to_string()
+					}
+				}
+				_ => {
+					error!("Somehow called an invalid /project subcommand: {}", sub.name);
+					return;
+				}
+			}
+		};
+
+		reply(&ctx, &command, content).await;
+	}
+
+	async fn handle_project_admin(ctx: Context, command: ApplicationCommandInteraction, guild: &'static GuildConfig) {
+		let sub = match command.data.options.first() {
+			Some(sub) => sub,
+			None => {
+				error!("Somehow called /project-admin with no subcommand!");
+				return;
+			}
+		};
+		let sub_option = |name: &str| sub.options.iter().find(|x| x.name == name).and_then(|x| x.resolved.as_ref());
+		let category = match sub_option("category") {
+			Some(CommandDataOptionValue::Channel(channel)) => Some(channel.id),
+			_ => None,
+		};
+
+		debug!("{} called /project-admin {}", command.user.tag(), sub.name);
+
+		let content = match sub.name.as_str() {
+			"create" => {
+				let name = match sub_option("name") {
+					Some(CommandDataOptionValue::String(name)) => name.trim().to_string(),
+					_ => {
+						error!("Somehow called /project-admin create with no name!");
+						return;
+					}
+				};
+				let lead = match sub_option("lead") {
+					Some(CommandDataOptionValue::User(user, _)) => Some(user.clone()),
+					_ => None,
+				};
+
+				if name.is_empty() {
+					reply(&ctx, &command, "Error: a project needs a name!").await;
+					return;
+				}
+
+				// Creating a role and a channel can take a moment.
+				command.defer_ephemeral(&ctx.http).await.unwrap();
+
+				info!("{} is creating project {}", command.user.tag(), name);
+				match projects::create(&ctx, guild, &name, category, lead.as_ref(), &command.user.tag()).await {
+					Ok((role, channel)) => format!("Created <@&{}> with <#{}>.", role, channel),
+					Err(err) => {
+						error!("Error creating project {}: {}", name, err);
+						err
+					}
+				}
+			}
+			"archive" => {
+				let project = match sub_option("project") {
+					Some(CommandDataOptionValue::Role(role)) => role.clone(),
+					_ => {
+						error!("Somehow called /project-admin archive with no project!");
+						return;
+					}
+				};
+
+				if !guild.role_list(RoleList::Projects).contains(&project.id) {
+					reply(&ctx, &command, format!("Error: <@&{}> is not a project!", project.id)).await;
+					return;
+				}
+				let category = match category.or(guild.archive_category) {
+					Some(category) => category,
+					None => {
+						reply(&ctx, &command, "Error: there's no archive category, so pick one.").await;
+						return;
+					}
+				};
+
+				// Taking the role from every member can take a while.
+				command.defer_ephemeral(&ctx.http).await.unwrap();
+
+				info!("{} is archiving project {}", command.user.tag(), project.name);
+				match projects::archive(&ctx, guild, &project, category, &command.user.tag()).await {
+					Ok(members) => format!("Archived {}, saving its roster of {} members.", project.name, members),
+					Err(err) => {
+						error!("Error archiving project {}: {}", project.name, err);
+						err
+					}
+				}
+			}
+			_ => {
+				error!("Somehow called an invalid /project-admin subcommand: {}", sub.name);
+				return;
+			}
+		};
+
+		command.edit_original_interaction_response(&ctx.http, |r| {
+			r.content(content)
+		}).await.unwrap();
+	}
+
+	async fn handle_migrate_access(ctx: Context, command: ApplicationCommandInteraction, guild: &'static GuildConfig) {
+		debug!("{} called /migrate-access", command.user.tag());
+
+		// Migrating can take a while on busy channels, so don't let the interaction time out.
+		command.defer_ephemeral(&ctx.http).await.unwrap();
+
+		let chans = guild.id.channels(&ctx).await.unwrap();
+		let chans: Vec<_> = chans
+			.values()
+			.filter(|x| chan_access(x) == ChannelAccess::Role)
+			.collect();
+
+		let (mut count, mut skipped) = (0, 0);
+		for chan in &chans {
+			let (converted, left) = migrate_chan_access(&ctx, chan).await;
+			count += converted;
+			skipped += left;
+		}
+
+		info!("{} migrated {} member overwrites across {} channels, leaving {} alone", command.user.tag(), count, chans.len(), skipped);
+
+		command.edit_original_interaction_response(&ctx.http, |r| {
+			r.content(format!("Converted {} member overwrites into access roles across {} channels. Left {} overwrites that do more than grant access alone.", count, chans.len(), skipped))
+		}).await.unwrap();
+	}
+
+	async fn handle_reconcile(ctx: Context, command: ApplicationCommandInteraction, guild: &'static GuildConfig) {
+		let update = option(&command, "update_records")
+			.and_then(|x| x.value.as_ref())
+			.and_then(|x| x.as_bool())
+			.unwrap_or(false);
+
+		debug!("{} called /reconcile with update_records: {}", command.user.tag(), update);
+
+		command.defer_ephemeral(&ctx.http).await.unwrap();
+
+		let drift = match find_drift(&ctx, guild).await {
+			Ok(drift) => drift,
+			Err(why) => {
+				error!("Error looking for drift: {}", why);
+				command.edit_original_interaction_response(&ctx.http, |r| {
+					r.content(format!("{}. Try again in a minute.", why))
+				}).await.unwrap();
+				return;
+			}
+		};
+
+		let mut summary = format!("Found {} mismatches between Discord and Mission Control's records.", drift.len());
+		if update {
+			let updated = update_records(&ctx, &drift).await;
+			info!("{} updated {} of {} mismatched records", command.user.tag(), updated, drift.len());
+			summary.push_str(&format!(" Updated {} of our records to match Discord; nothing on Discord was changed, and moderator overwrites were left alone.", updated));
+		}
+
+		command.edit_original_interaction_response(&ctx.http, |r| {
+			r.content(summary)
+		}).await.unwrap();
+
+		if drift.is_empty() {
+			return;
+		}
+
+		// The full report easily outgrows a message, so send it as a file.
+		let report: String = drift.iter().map(|x| format!("{}\n", x)).collect();
+		command.create_followup_message(&ctx.http, |f| {
+			f.flags(MessageFlags::EPHEMERAL);
+			f.add_file(AttachmentType::Bytes { data: report.into_bytes().into(), filename: "reconcile.txt".to_string() })
+		}).await.unwrap();
+	}
+
+	async fn handle_import(ctx: Context, command: ApplicationCommandInteraction, guild: &'static GuildConfig) {
+		let attachment = match option(&command, "roster").and_then(|x| x.resolved.as_ref()) {
+			Some(CommandDataOptionValue::Attachment(a)) => a.clone(),
+			_ => {
+				error!("Somehow called /import with no roster!");
+				return;
+			}
+		};
+		let term = option(&command, "term")
+			.and_then(|x| x.value.as_ref())
+			.and_then(|x| x.as_str())
+			.map(|x| x.to_string())
+			.unwrap_or_else(current_term);
+		let dry_run = option(&command, "dry_run")
+			.and_then(|x| x.value.as_ref())
+			.and_then(|x| x.as_bool())
+			.unwrap_or(false);
+
+		debug!("{} called /import with {} for {} (dry run: {})", command.user.tag(), attachment.filename, term, dry_run);
 
+		command.defer_ephemeral(&ctx.http).await.unwrap();
+
+		let rows = match attachment.download().await.map(|x| parse_roster(&x)) {
+			Ok(Ok(rows)) => rows,
+			_ => {
+				command.edit_original_interaction_response(&ctx.http, |r| {
+					r.content(format!("Error: couldn't read {} as a CSV roster!", attachment.filename))
+				}).await.unwrap();
+				return;
+			}
+		};
+
+		let members = match fetch_members(&ctx.http, guild.id).await {
+			Ok(members) => members,
+			Err(_) => {
+				error!("Error retrieving members for the {} roster import", term);
+				command.edit_original_interaction_response(&ctx.http, |r| {
+					r.content("Error: couldn't retrieve the member list, try again in a minute.")
+				}).await.unwrap();
+				return;
+			}
+		};
+		let plan = plan_import(guild, &rows, &members);
+		let preview = format!("**Roster import for {}**\n{}", term, plan.preview(20));
+
+		let mess = command.edit_original_interaction_response(&ctx.http, |r| {
+			r.content(&preview);
+			if !dry_run {
+				r.components(|c| {
+					c.create_action_row(|ar| {
+						ar
+							.create_button(|b| { b.custom_id("apply").label("Apply").style(ButtonStyle::Success) })
+							.create_button(|b| { b.custom_id("cancel").label("Cancel").style(ButtonStyle::Secondary) })
+					})
+				});
+			}
+			r
+		}).await.unwrap();
+
+		if dry_run {
+			return;
+		}
+
+		let mci = match mess.await_component_interaction(&ctx).timeout(Duration::from_secs(600)).await {
+			Some(mci) => mci,
+			None => {
+				command.edit_original_interaction_response(&ctx.http, |r| {
+					r.content(format!("{}\nTimed out; nothing was changed.", preview)).components(|c| c)
+				}).await.unwrap();
+				return;
+			}
+		};
+
+		if mci.data.custom_id != "apply" {
+			mci.create_interaction_response(&ctx.http, |r| {
+				r.kind(InteractionResponseType::UpdateMessage);
+				r.interaction_response_data(|d| d.content(format!("{}\nCancelled; nothing was changed.", preview)).components(|c| c))
+			}).await.unwrap();
+			return;
+		}
+
+		mci.create_interaction_response(&ctx.http, |r| {
+			r.kind(InteractionResponseType::UpdateMessage);
+			r.interaction_response_data(|d| d.content(format!("{}\nApplying...", preview)).components(|c| c))
+		}).await.unwrap();
+
+		info!("{} is importing a roster of {} rows for {}", command.user.tag(), rows.len(), term);
+		let (ok, failed) = apply_import(&ctx, &*db(&ctx).await, guild, &plan, &term).await;
+
+		command.edit_original_interaction_response(&ctx.http, |r| {
+			r.content(format!("{}\nDone! Promoted {} members ({} failed).", preview, ok, failed))
+		}).await.unwrap();
+	}
+
+	async fn handle_export(ctx: Context, command: ApplicationCommandInteraction, guild: &'static GuildConfig) {
+		let format = option(&command, "format")
+			.and_then(|x| x.value.as_ref())
+			.and_then(|x| x.as_str())
+			.unwrap_or("csv")
+			.to_string();
+		let project = match option(&command, "project").and_then(|x| x.resolved.as_ref()) {
+			Some(CommandDataOptionValue::Role(role)) => Some(role.id),
+			_ => None,
+		};
+
+		debug!("{} called /export with format {} and project {:?}", command.user.tag(), format, project);
+
+		if let Some(project) = project {
+			if !guild.role_list(RoleList::Projects).contains(&project) {
 				command.create_interaction_response(&ctx.http, |r| {
 					r.kind(InteractionResponseType::ChannelMessageWithSource);
 					r.interaction_response_data(|d| {
-						d.flags(InteractionApplicationCommandCallbackDataFlags::EPHEMERAL);
-						d.content(format!("You've successfully left #{}!", chan.name))
+						d.flags(MessageFlags::EPHEMERAL);
+						d.content(format!("Error: <@&{}> is not a project!", project))
 					})
 				}).await.unwrap();
+				return;
+			}
+		}
+
+		command.defer_ephemeral(&ctx.http).await.unwrap();
+
+		let rows = match build_export(&ctx.http, guild, project).await {
+			Ok(rows) => rows,
+			Err(_) => {
+				error!("Error building the roster export");
+				command.edit_original_interaction_response(&ctx.http, |r| {
+					r.content("Error: couldn't retrieve the member list, try again in a minute.")
+				}).await.unwrap();
+				return;
+			}
+		};
+		let data = match format.as_str() {
+			"json" => export_json(&rows),
+			_ => export_csv(&rows),
+		};
+
+		command.edit_original_interaction_response(&ctx.http, |r| {
+			r.content(format!("Exported {} members.", rows.len()))
+		}).await.unwrap();
+
+		command.create_followup_message(&ctx.http, |f| {
+			f.flags(MessageFlags::EPHEMERAL);
+			f.add_file(AttachmentType::Bytes { data: data.into(), filename: format!("roster.{}", format) })
+		}).await.unwrap();
+	}
+
+	async fn handle_stats(ctx: Context, command: ApplicationCommandInteraction, guild: &'static GuildConfig) {
+		let csv = option(&command, "csv")
+			.and_then(|x| x.value.as_ref())
+			.and_then(|x| x.as_bool())
+			.unwrap_or(false);
+
+		debug!("{} called /stats with csv: {}", command.user.tag(), csv);
+
+		command.defer_ephemeral(&ctx.http).await.unwrap();
+
+		let stats = match stats::gather(&ctx, guild).await {
+			Some(stats) => stats,
+			None => {
+				error!("Error gathering stats");
+				command.edit_original_interaction_response(&ctx.http, |r| {
+					r.content("Error: couldn't gather stats, try again in a minute.")
+				}).await.unwrap();
+				return;
+			}
+		};
+
+		command.edit_original_interaction_response(&ctx.http, |r| {
+			r.embed(|e| {
+				e.title("Mission Control stats");
+				for (name, value) in stats.fields() {
+					e.field(name, value, true);
+				}
+				e
+			})
+		}).await.unwrap();
+
+		if csv {
+			command.create_followup_message(&ctx.http, |f| {
+				f.flags(MessageFlags::EPHEMERAL);
+				f.add_file(AttachmentType::Bytes { data: stats.csv().into(), filename: "stats.csv".to_string() })
+			}).await.unwrap();
+		}
+	}
+
+	async fn handle_rollover(ctx: Context, command: ApplicationCommandInteraction, guild: &'static GuildConfig) {
+		let sub = match command.data.options.first() {
+			Some(sub) => sub,
+			None => {
+				error!("Somehow called /rollover with no subcommand!");
+				return;
 			}
+		};
+		let sub_option = |name: &str| sub.options.iter().find(|x| x.name == name).and_then(|x| x.resolved.as_ref());
+
+		debug!("{} called /rollover {}", command.user.tag(), sub.name);
+
+		if !guild.rollover {
+			reply(&ctx, &command, "Memberships don't roll over in this server.").await;
+			return;
 		}
+
+		let content = match sub.name.as_str() {
+			"contested" => {
+				let contested = rollover::contested(&db(&ctx).await.lock().unwrap());
+				if contested.is_empty() {
+					"Nobody has a contested rollover.".to_string()
+				} else {
+					// Keep well inside a message's length limit.
+					let mut out: String = contested.iter().take(40).map(|(user, term, change)| format!("<@{}> ({}): {}\n", user, term, change)).collect();
+					if contested.len() > 40 {
+						out.push_str(&format!("...and {} more", contested.len() - 40));
+					}
+					out
+				}
+			}
+			"resolve" => {
+				let user = match sub_option("member") {
+					Some(CommandDataOptionValue::User(user, _)) => user.clone(),
+					_ => {
+						error!("Somehow called /rollover resolve with no member!");
+						return;
+					}
+				};
+				let apply = matches!(sub_option("apply"), Some(CommandDataOptionValue::Boolean(true)));
+
+				// Applying changes roles, which can take a moment.
+				command.defer_ephemeral(&ctx.http).await.unwrap();
+
+				info!("{} is resolving {}'s contested rollover (apply: {})", command.user.tag(), user.tag(), apply);
+				let content = match rollover::resolve(&ctx, guild, user.id, apply, &command.user.tag()).await {
+					(0, _) => format!("<@{}> has no contested rollover.", user.id),
+					(_, 0) if apply => format!("Applied <@{}>'s rollover.", user.id),
+					(_, _) if apply => format!("Error: couldn't change <@{}>'s membership; it'll be retried with the rest of the rollover.", user.id),
+					_ => format!("Dismissed <@{}>'s rollover; they keep their membership.", user.id),
+				};
+
+				command.edit_original_interaction_response(&ctx.http, |r| r.content(content)).await.unwrap();
+				return;
+			}
+			_ => {
+				error!("Somehow called an invalid /rollover subcommand: {}", sub.name);
+				return;
+			}
+		};
+
+		reply(&ctx, &command, content).await;
+	}
+
+	async fn handle_jobs(&self, ctx: Context, command: ApplicationCommandInteraction, guild: &'static GuildConfig) {
+		let sub = match command.data.options.first() {
+			Some(sub) => sub,
+			None => {
+				error!("Somehow called /jobs with no subcommand!");
+				return;
+			}
+		};
+
+		debug!("{} called /jobs {}", command.user.tag(), sub.name);
+
+		let content = match sub.name.as_str() {
+			"list" => {
+				let jobs = list_jobs(&db(&ctx).await.lock().unwrap(), guild.id);
+
+				let mut out = String::new();
+				for job in &jobs {
+					let schedule = job.schedule.as_deref().unwrap_or("once");
+					let last = match (job.last_run, &job.last_status) {
+						(Some(at), Some(status)) => format!("last ran {} ({})", discord_time(at), status),
+						_ => "never ran".to_string(),
+					};
+					let running = if self.scheduler.is_running(&job.name) { " **running**" } else { "" };
+
+					out.push_str(&format!("`{}` `{}`: next {}, {}{}\n", job.name, schedule, discord_time(job.next_run), last, running));
+				}
+
+				if out.is_empty() {
+					"No jobs are scheduled.".to_string()
+				} else {
+					out
+				}
+			}
+			"run" => {
+				let task = sub.options.first().and_then(|x| x.value.as_ref()).and_then(|x| x.as_str()).unwrap_or_default();
+				let scheduled = schedule_once(&db(&ctx).await.lock().unwrap(), task, guild.id, Utc::now().timestamp());
+
+				match scheduled {
+					Ok(name) => format!("Queued `{}`; it'll start within a minute.", name),
+					Err(_) => {
+						error!("Error queueing task {}", task);
+						"Error: couldn't queue that task.".to_string()
+					}
+				}
+			}
+			_ => {
+				error!("Somehow called an invalid /jobs subcommand: {}", sub.name);
+				return;
+			}
+		};
+
+		command.create_interaction_response(&ctx.http, |r| {
+			r.kind(InteractionResponseType::ChannelMessageWithSource);
+			r.interaction_response_data(|d| {
+				d.flags(MessageFlags::EPHEMERAL);
+				d.content(content)
+			})
+		}).await.unwrap();
+	}
+}
+
+/// Reply to a command with a message only its user can see.
+async fn reply(ctx: &Context, command: &ApplicationCommandInteraction, content: impl ToString) {
+	command.create_interaction_response(&ctx.http, |r| {
+		r.kind(InteractionResponseType::ChannelMessageWithSource);
+		r.interaction_response_data(|d| {
+			d.flags(MessageFlags::EPHEMERAL);
+			d.content(content)
+		})
+	}).await.unwrap();
+}
+
+/// Join a list for an embed field, which can't be empty.
+fn list_or_none(items: impl Iterator<Item = String>) -> String {
+	let items: Vec<_> = items.collect();
+	if items.is_empty() {
+		return "None".to_string();
 	}
+
+	items.join("\n")
+}
+
+/// Find a top-level option of a command by name.
+fn option<'a>(command: &'a ApplicationCommandInteraction, name: &str) -> Option<&'a CommandDataOption> {
+	command.data.options.iter().find(|x| x.name == name)
 }
\ No newline at end of file
@@ -1,15 +1,60 @@
 use serenity::client::Context;
-use serenity::model::interactions::message_component::MessageComponentInteraction;
+use serenity::model::application::interaction::{InteractionResponseType, MessageFlags};
+use serenity::model::application::interaction::message_component::MessageComponentInteraction;
 
 use crate::bot::Bot;
+use crate::bot::config::guild_config;
 use crate::bot::mc::MC;
+use crate::bot::metrics;
+use crate::bot::permissions;
+use crate::bot::rollover;
 
 impl Bot {
 	pub async fn handle_component(&self, ctx: Context, component: MessageComponentInteraction) {
 		trace!("Handling component {} from {}", component.data.custom_id, component.user.tag());
+		let timer = metrics::LATENCY.with_label_values(&["component"]).start_timer();
+
 		match component.data.custom_id.as_str() {
-			"launch-mc" => MC::from_component(ctx, component).await,
-			_ => {}
+			// MC times its own responses, since the session outlives this call.
+			"launch-mc" => {
+				timer.stop_and_discard();
+				let guild = match component.guild_id.and_then(guild_config) {
+					Some(guild) => guild,
+					None => {
+						warn!("{} launched MC outside a configured guild", component.user.tag());
+						component.create_interaction_response(&ctx.http, |r| {
+							r.kind(InteractionResponseType::ChannelMessageWithSource);
+							r.interaction_response_data(|d| {
+								d.flags(MessageFlags::EPHEMERAL);
+								d.content("Mission Control isn't set up for this server.")
+							})
+						}).await.unwrap();
+						return;
+					}
+				};
+				// The button launches the same thing as /mc, so it needs the same access.
+				if !permissions::allowed(guild, "mc", component.member.as_ref()) {
+					permissions::denied(guild, "mc", component.member.as_ref());
+					component.create_interaction_response(&ctx.http, |r| {
+						r.kind(InteractionResponseType::ChannelMessageWithSource);
+						r.interaction_response_data(|d| {
+							d.flags(MessageFlags::EPHEMERAL);
+							d.content("You don't have permission to use Mission Control.")
+						})
+					}).await.unwrap();
+					return;
+				}
+				MC::from_component(ctx, component, guild, &self.sessions).await;
+				return;
+			}
+			id if id.starts_with("contest-rollover:") => rollover::handle_contest(ctx, component).await,
+			// Buttons on a live MC menu are picked up by its session, not here.
+			_ => {
+				timer.stop_and_discard();
+				return;
+			}
 		}
+
+		timer.observe_duration();
 	}
 }
\ No newline at end of file
@@ -1,34 +1,225 @@
+use std::time::Duration;
+
 use serenity::model::id::{ChannelId, GuildId, RoleId};
+use serenity::model::Permissions;
+
+use crate::bot::settings::{self, Setting};
 
 pub const SEND_INTRO: bool = false;
 
-pub const GUILD_ID: GuildId = GuildId(491275273598402561);
+/// Everything Mission Control needs to know about one guild it runs in.
+pub struct GuildConfig {
+	pub id: GuildId,
+	/// Where the "Launch Mission Control" message goes when `SEND_INTRO` is set.
+	pub intro_channel: Option<ChannelId>,
+	/// Channels that are never listed, even under a joinable category. /mcadmin can change this;
+	/// read it through `excluded_channels()`.
+	pub excluded_channels: &'static [ChannelId],
+	pub member: RoleId,
+	pub alumni: RoleId,
+	pub friend: RoleId,
+	/// Every membership role; a user holds at most one of these. /mcadmin can change this and the
+	/// next two lists; read them through `role_list()`.
+	pub memberships: &'static [RoleId],
+	pub roles: &'static [RoleId],
+	pub projects: &'static [RoleId],
+	/// Where the bot posts things officers need to look at, like contested rollovers.
+	pub officer_channel: Option<ChannelId>,
+	/// Where the bot reports members abusing Mission Control, like hitting the rate limit over and
+	/// over.
+	pub mod_channel: Option<ChannelId>,
+	/// Where `/project-admin archive` moves a project's channels, unless told otherwise, and where
+	/// inactive channels are archived to.
+	pub archive_category: Option<ChannelId>,
+	/// If set, the `inactive-channels` job moves listed channels nobody has posted in for this many
+	/// days to `archive_category` and excludes them, so they're no longer listed.
+	pub auto_archive_days: Option<i64>,
+	/// Holders of these roles count as officers, alongside anyone with `OFFICER_PERMISSIONS`.
+	pub officer_roles: &'static [RoleId],
+	/// Who may use each command here, where it differs from `COMMAND_ACCESS`.
+	pub command_access: &'static [(&'static str, Access)],
+	/// Whether this guild's memberships roll over each semester. Dues and graduations are recorded
+	/// per person, so at most one guild should have this set.
+	pub rollover: bool,
+	/// Every group shown on the Mission Control main menu, in display order.
+	pub assignables: &'static [Assignable],
+}
 
-pub const CAT_CHANNELS: ChannelId = ChannelId(614536824295260160);
+/// Every guild Mission Control runs in. The first is the default for command-line tasks.
+pub const GUILDS: &[GuildConfig] = &[
+	GuildConfig {
+		id: GuildId(491275273598402561),
+		intro_channel: Some(ChannelId(869756293894783006)),
+		excluded_channels: &[
+			ChannelId(669328124357640222), // Hydrazine
+		],
+		member: MEMBERSHIP_MEMBER,
+		alumni: MEMBERSHIP_ALUMNI,
+		friend: MEMBERSHIP_FRIEND,
+		memberships: ALLOWED_MEMBERSHIPS,
+		roles: ALLOWED_ROLES,
+		projects: ALLOWED_PROJECTS,
+		officer_channel: None,
+		mod_channel: None,
+		archive_category: None,
+		auto_archive_days: None,
+		officer_roles: &[],
+		command_access: &[],
+		rollover: true,
+		assignables: ASSIGNABLES,
+	},
+];
 
-pub const CAT_GAMES: ChannelId = ChannelId(696569774632861746);
+/// Look up a guild's configuration, if Mission Control runs there.
+pub fn guild_config(id: GuildId) -> Option<&'static GuildConfig> {
+	GUILDS.iter().find(|x| x.id == id)
+}
 
-pub const EXCLUDED_CHANNELS: &[ChannelId] = &[
-	ChannelId(669328124357640222), // Hydrazine
-];
+impl GuildConfig {
+	/// The roles in one of our lists, after any changes made through /mcadmin.
+	pub fn role_list(&self, list: RoleList) -> Vec<RoleId> {
+		let defaults = match list {
+			RoleList::Memberships => self.memberships,
+			RoleList::Roles => self.roles,
+			RoleList::Projects => self.projects,
+		};
+
+		settings::effective(self.id, &Setting::Roles(list).key(self), defaults)
+	}
+
+	/// Channels that are never listed, after any changes made through /mcadmin.
+	pub fn excluded_channels(&self) -> Vec<ChannelId> {
+		settings::effective(self.id, &Setting::Excluded.key(self), self.excluded_channels)
+	}
+
+	/// The categories an overwrite group lists, after any changes made through /mcadmin.
+	pub fn categories(&self, group: &Assignable) -> Vec<ChannelId> {
+		let source = match group.kind {
+			AssignableKind::ChannelOverwrite(source) => source,
+			_ => return vec![],
+		};
+
+		match self.assignables.iter().position(|x| x.id == group.id) {
+			Some(idx) => settings::effective(self.id, &Setting::Categories(idx).key(self), source.categories),
+			None => source.categories.to_vec(),
+		}
+	}
+
+	/// What's turned on here but has nowhere to post or move things, so it won't do anything.
+	pub fn missing_targets(&self) -> Vec<&'static str> {
+		let mut missing = vec![];
+
+		if self.officer_channel.is_none() {
+			missing.push("there's no officer_channel, so inactive channel reports aren't sent");
+			if self.rollover {
+				missing.push("rollover is on, but there's no officer_channel for contested rollovers");
+			}
+		}
+		if self.mod_channel.is_none() {
+			missing.push("there's no mod_channel, so rate limit alerts aren't sent");
+		}
+		if self.auto_archive_days.is_some() && self.archive_category.is_none() {
+			missing.push("auto_archive_days is set, but there's no archive_category to archive to");
+		}
 
-pub const MEMBERSHIP_MEMBER: RoleId = RoleId(585637350529302529);
-pub const MEMBERSHIP_ALUMNI: RoleId = RoleId(612059569274748969);
-pub const MEMBERSHIP_FRIEND: RoleId = RoleId(787427932346777660);
+		missing
+	}
+}
 
-pub const ALLOWED_MEMBERSHIPS: &[RoleId] = &[
+const CAT_CHANNELS: ChannelId = ChannelId(614536824295260160);
+
+const CAT_GAMES: ChannelId = ChannelId(696569774632861746);
+
+const MEMBERSHIP_MEMBER: RoleId = RoleId(585637350529302529);
+const MEMBERSHIP_ALUMNI: RoleId = RoleId(612059569274748969);
+const MEMBERSHIP_FRIEND: RoleId = RoleId(787427932346777660);
+
+const ALLOWED_MEMBERSHIPS: &[RoleId] = &[
 	MEMBERSHIP_MEMBER, // SEDS Member
 	MEMBERSHIP_ALUMNI, // SEDS Alumnus
 	MEMBERSHIP_FRIEND, // Friend of SEDS
 ];
 
-pub const ALLOWED_ROLES: &[RoleId] = &[
+/// Who may use a command.
+#[derive(Copy, Clone, PartialEq)]
+pub enum Access {
+	Everyone,
+	/// Anyone with one of the guild's `officer_roles` or with `OFFICER_PERMISSIONS`.
+	Officer,
+	/// Anyone with all of these Discord permissions.
+	Permissions(Permissions),
+}
+
+/// The Discord permissions that make someone an officer, whatever their roles.
+pub const OFFICER_PERMISSIONS: Permissions = Permissions::ADMINISTRATOR;
+
+/// Who may use each command, unless a guild's `command_access` says otherwise. Commands missing
+/// from here are officer-only.
+pub const COMMAND_ACCESS: &[(&str, Access)] = &[
+	("mc", Access::Everyone),
+	("become", Access::Everyone),
+	("join", Access::Everyone),
+	("leave", Access::Everyone),
+	// Editing a project is further limited to its leads and officers.
+	("project", Access::Everyone),
+	("mcadmin", Access::Officer),
+	("migrate-access", Access::Permissions(Permissions::MANAGE_ROLES.union(Permissions::MANAGE_CHANNELS))),
+	("reconcile", Access::Officer),
+	("import", Access::Officer),
+	("export", Access::Officer),
+	("stats", Access::Officer),
+	("project-admin", Access::Officer),
+	("jobs", Access::Officer),
+	("rollover", Access::Officer),
+];
+
+/// Each user can make this many role, channel and subscription changes in a burst...
+pub const RATE_USER_BURST: u32 = 10;
+/// ...then get one more each this often.
+pub const RATE_USER_REFILL: Duration = Duration::from_secs(6);
+
+/// Everyone together can make this many changes in a burst, to keep us clear of Discord's limits...
+pub const RATE_GLOBAL_BURST: u32 = 60;
+/// ...then get one more each this often.
+pub const RATE_GLOBAL_REFILL: Duration = Duration::from_millis(500);
+
+/// A user refused this many times within `RATE_ALERT_WINDOW` gets reported to the `mod_channel`.
+pub const RATE_ALERT_STRIKES: u32 = 5;
+pub const RATE_ALERT_WINDOW: Duration = Duration::from_secs(600);
+
+/// How many members must request the same channel before it's created.
+pub const CHANNEL_REQUEST_THRESHOLD: usize = 5;
+
+/// The (month, day) each semester's membership rollover happens. Members without dues recorded
+/// for the new term become Friends, and members whose graduation term has passed become Alumni.
+pub const ROLLOVER_DATES: &[(u32, u32)] = &[
+	(1, 20), // Spring
+	(8, 25), // Fall
+];
+
+/// The first month of the Fall term; earlier months of the year are Spring. It has to fall on or
+/// before the Fall rollover above, so that rollover moves members into Fall.
+pub const FALL_START_MONTH: u32 = 8;
+
+/// How many days before a rollover affected members are warned by DM.
+pub const ROLLOVER_NOTICE_DAYS: i64 = 7;
+
+/// Recurring background jobs as (task name, cron schedule with seconds), in UTC.
+pub const SCHEDULED_JOBS: &[(&str, &str)] = &[
+	("rollover", "0 0 * * * *"), // Hourly
+	("inactive-channels", "0 0 15 * * Mon"), // Weekly
+];
+
+/// Listed channels nobody has posted in for this many days are reported to officers.
+pub const INACTIVE_REPORT_DAYS: i64 = 90;
+
+const ALLOWED_ROLES: &[RoleId] = &[
 	RoleId(621586486793601044), // Industry Pro
 	RoleId(709650648421105694), // Industry Intern
 	RoleId(759187648799178785), // Student Researcher
 ];
 
-pub const ALLOWED_PROJECTS: &[RoleId] = &[
+const ALLOWED_PROJECTS: &[RoleId] = &[
 	RoleId(787477836171968552),  // RASC-AL
 	RoleId(585634734122467339),  // IREC
 	RoleId(787478051414867978),  // Sojourner
@@ -38,4 +229,160 @@ pub const ALLOWED_PROJECTS: &[RoleId] = &[
 	RoleId(1017839639463207012), // L1 Rocketeer
 ];
 
-pub const MAX_LIST_SIZE: usize = 20;
\ No newline at end of file
+/// One of a guild's lists of self-assignable roles.
+#[derive(Copy, Clone, PartialEq)]
+pub enum RoleList {
+	Memberships,
+	Roles,
+	Projects,
+}
+
+/// How an assignable group grants membership, and where its options come from.
+#[derive(Copy, Clone, PartialEq)]
+pub enum AssignableKind {
+	/// Joining gives the user one of the roles in this list.
+	Role(RoleList),
+	/// Joining gives the user a permission overwrite on one of these channels.
+	ChannelOverwrite(ChannelSource),
+	/// Joining adds the user to an active public thread under a joinable channel.
+	Thread,
+	/// Joining subscribes the user to a tag in a joinable forum, adding them to every new post with it.
+	ForumTag,
+}
+
+/// Where an overwrite-based group finds its channels.
+///
+/// Besides what's configured here, officers can manage joinability from Discord by putting tags in
+/// a channel's topic: `[mc:hidden]` hides a channel, `[mc:joinable]` opts a channel in to an
+/// `opt_in` group, and `[mc:joinable:<group id>]` adds a channel anywhere in the guild to a group.
+#[derive(Copy, Clone, PartialEq)]
+pub struct ChannelSource {
+	/// Every channel under these categories is listed. /mcadmin can change this; read it through
+	/// `GuildConfig::categories()`.
+	pub categories: &'static [ChannelId],
+	/// Extra channels listed regardless of their category.
+	pub channels: &'static [ChannelId],
+	/// If set, channels under `categories` must also be tagged `[mc:joinable]` to be listed.
+	pub opt_in: bool,
+	/// How joining one of these channels grants access.
+	pub access: ChannelAccess,
+	/// If set, members can request new channels here. Once `CHANNEL_REQUEST_THRESHOLD` of them
+	/// ask for the same one, it's created under the first of `categories`.
+	pub requestable: bool,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum ChannelAccess {
+	/// Each joiner gets their own member permission overwrite on the channel.
+	Overwrite,
+	/// Each channel gets a backing "access role" with one overwrite, and joiners get the role.
+	Role,
+}
+
+/// A group of things a user can add or remove themselves from through Mission Control.
+pub struct Assignable {
+	/// The custom ID of this group's main menu button; must be unique.
+	pub id: &'static str,
+	/// The label of this group's main menu button.
+	pub name: &'static str,
+	pub kind: AssignableKind,
+	pub add_label: &'static str,
+	pub remove_label: &'static str,
+	pub add_placeholder: &'static str,
+	pub remove_placeholder: &'static str,
+}
+
+impl Assignable {
+	/// Can members request new channels in this group?
+	pub fn requestable(&self) -> bool {
+		matches!(self.kind, AssignableKind::ChannelOverwrite(source) if source.requestable)
+	}
+}
+
+const ASSIGNABLES: &[Assignable] = &[
+	Assignable {
+		id: "roles",
+		name: "Roles",
+		kind: AssignableKind::Role(RoleList::Roles),
+		add_label: "Add Roles",
+		remove_label: "Remove Roles",
+		add_placeholder: "Select a role to add...",
+		remove_placeholder: "Select a role to remove...",
+	},
+	Assignable {
+		id: "chans",
+		name: "Channels",
+		kind: AssignableKind::ChannelOverwrite(ChannelSource {
+			categories: &[CAT_CHANNELS],
+			channels: &[],
+			opt_in: false,
+			access: ChannelAccess::Overwrite,
+			requestable: false,
+		}),
+		add_label: "Join Channels",
+		remove_label: "Leave Channels",
+		add_placeholder: "Select a channel to join...",
+		remove_placeholder: "Select a channel to leave...",
+	},
+	Assignable {
+		id: "projs",
+		name: "Projects",
+		kind: AssignableKind::Role(RoleList::Projects),
+		add_label: "Join Projects",
+		remove_label: "Leave Projects",
+		add_placeholder: "Select a project to join...",
+		remove_placeholder: "Select a project to leave...",
+	},
+	Assignable {
+		id: "games",
+		name: "Games",
+		kind: AssignableKind::ChannelOverwrite(ChannelSource {
+			categories: &[CAT_GAMES],
+			channels: &[],
+			opt_in: false,
+			access: ChannelAccess::Overwrite,
+			requestable: true,
+		}),
+		add_label: "Add Games",
+		remove_label: "Remove Games",
+		add_placeholder: "Select a game to add...",
+		remove_placeholder: "Select a game to remove...",
+	},
+	Assignable {
+		id: "threads",
+		name: "Threads",
+		kind: AssignableKind::Thread,
+		add_label: "Follow Threads",
+		remove_label: "Unfollow Threads",
+		add_placeholder: "Select a thread to follow...",
+		remove_placeholder: "Select a thread to unfollow...",
+	},
+	Assignable {
+		id: "tags",
+		name: "Forum Tags",
+		kind: AssignableKind::ForumTag,
+		add_label: "Subscribe to Tags",
+		remove_label: "Unsubscribe from Tags",
+		add_placeholder: "Select a forum tag to subscribe to...",
+		remove_placeholder: "Select a forum tag to unsubscribe from...",
+	},
+];
+
+pub const MAX_LIST_SIZE: usize = 20;
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn assignable_ids_are_unique() {
+		for guild in GUILDS {
+			let ids: Vec<_> = guild.assignables.iter().map(|x| x.id).collect();
+			for (i, id) in ids.iter().enumerate() {
+				// The main menu uses these for its own buttons.
+				assert!(!["membership", "exit-mc"].contains(id), "{} is reserved", id);
+				assert!(!ids[..i].contains(id), "{} is used twice in guild {}", id, guild.id);
+			}
+		}
+	}
+}
\ No newline at end of file
@@ -0,0 +1,233 @@
+use std::env;
+use std::sync::{Arc, Mutex};
+
+use chrono::Utc;
+use rusqlite::{Connection, params};
+use serenity::client::Context;
+use serenity::model::id::UserId;
+use serenity::prelude::TypeMapKey;
+
+use crate::bot::config::GUILDS;
+use crate::bot::settings;
+
+/// The TypeMap key for our SQLite connection, shared through the Serenity context.
+pub struct Database;
+
+impl TypeMapKey for Database {
+	type Value = Arc<Mutex<Connection>>;
+}
+
+/// Open the database at `DB_PATH` (or `mc.db`), make sure every table exists, and load the
+/// settings changed through /mcadmin.
+pub fn open() -> Connection {
+	let path = env::var("DB_PATH").unwrap_or_else(|_| "mc.db".to_string());
+
+	let conn = Connection::open(&path).expect("Error opening the database");
+	migrate(&conn);
+	settings::load(&conn);
+
+	info!("Opened database at {}", path);
+
+	conn
+}
+
+/// An empty in-memory database with every table, for tests.
+#[cfg(test)]
+pub fn open_memory() -> Connection {
+	let conn = Connection::open_in_memory().unwrap();
+	migrate(&conn);
+	conn
+}
+
+fn migrate(conn: &Connection) {
+	conn.execute_batch("
+		CREATE TABLE IF NOT EXISTS tag_subscriptions (
+			user_id INTEGER NOT NULL,
+			forum_id INTEGER NOT NULL,
+			tag_id INTEGER NOT NULL,
+			PRIMARY KEY (user_id, forum_id, tag_id)
+		);
+
+		CREATE TABLE IF NOT EXISTS access_roles (
+			channel_id INTEGER PRIMARY KEY,
+			role_id INTEGER NOT NULL
+		);
+
+		CREATE TABLE IF NOT EXISTS overwrites (
+			channel_id INTEGER NOT NULL,
+			user_id INTEGER NOT NULL,
+			created_at INTEGER NOT NULL,
+			PRIMARY KEY (channel_id, user_id)
+		);
+
+		CREATE TABLE IF NOT EXISTS role_grants (
+			user_id INTEGER NOT NULL,
+			role_id INTEGER NOT NULL,
+			created_at INTEGER NOT NULL,
+			PRIMARY KEY (user_id, role_id)
+		);
+
+		CREATE TABLE IF NOT EXISTS dues (
+			user_id INTEGER NOT NULL,
+			term TEXT NOT NULL,
+			PRIMARY KEY (user_id, term)
+		);
+
+		CREATE TABLE IF NOT EXISTS graduations (
+			user_id INTEGER PRIMARY KEY,
+			term TEXT NOT NULL
+		);
+
+		CREATE TABLE IF NOT EXISTS audit_log (
+			id INTEGER PRIMARY KEY AUTOINCREMENT,
+			at INTEGER NOT NULL,
+			actor TEXT NOT NULL,
+			user_id INTEGER,
+			action TEXT NOT NULL,
+			detail TEXT NOT NULL
+		);
+
+		CREATE TABLE IF NOT EXISTS rollover_runs (
+			term TEXT PRIMARY KEY,
+			notified_at INTEGER NOT NULL
+		);
+
+		CREATE TABLE IF NOT EXISTS mc_sessions (
+			id TEXT PRIMARY KEY,
+			user_id INTEGER NOT NULL,
+			started_at INTEGER NOT NULL,
+			guild_id INTEGER
+		);
+
+		CREATE TABLE IF NOT EXISTS jobs (
+			name TEXT PRIMARY KEY,
+			schedule TEXT,
+			task TEXT NOT NULL,
+			payload TEXT NOT NULL,
+			next_run INTEGER NOT NULL,
+			last_run INTEGER,
+			last_status TEXT
+		);
+
+		CREATE TABLE IF NOT EXISTS setting_overrides (
+			guild_id INTEGER NOT NULL,
+			setting TEXT NOT NULL,
+			value INTEGER NOT NULL,
+			included INTEGER NOT NULL,
+			PRIMARY KEY (guild_id, setting, value)
+		);
+
+		CREATE TABLE IF NOT EXISTS project_profiles (
+			role_id INTEGER PRIMARY KEY,
+			description TEXT,
+			meeting TEXT,
+			links TEXT NOT NULL DEFAULT ''
+		);
+
+		CREATE TABLE IF NOT EXISTS project_leads (
+			role_id INTEGER NOT NULL,
+			user_id INTEGER NOT NULL,
+			PRIMARY KEY (role_id, user_id)
+		);
+
+		CREATE TABLE IF NOT EXISTS project_channels (
+			role_id INTEGER NOT NULL,
+			channel_id INTEGER NOT NULL,
+			PRIMARY KEY (role_id, channel_id)
+		);
+
+		CREATE TABLE IF NOT EXISTS project_archives (
+			role_id INTEGER PRIMARY KEY,
+			name TEXT NOT NULL,
+			archived_at INTEGER NOT NULL
+		);
+
+		CREATE TABLE IF NOT EXISTS project_archive_members (
+			role_id INTEGER NOT NULL,
+			user_id INTEGER NOT NULL,
+			lead INTEGER NOT NULL,
+			PRIMARY KEY (role_id, user_id)
+		);
+
+		CREATE TABLE IF NOT EXISTS channel_requests (
+			guild_id INTEGER NOT NULL,
+			group_id TEXT NOT NULL,
+			name TEXT NOT NULL,
+			user_id INTEGER NOT NULL,
+			requested_at INTEGER NOT NULL,
+			PRIMARY KEY (guild_id, group_id, name, user_id)
+		);
+
+		CREATE TABLE IF NOT EXISTS channel_request_claims (
+			guild_id INTEGER NOT NULL,
+			group_id TEXT NOT NULL,
+			name TEXT NOT NULL,
+			claimed_at INTEGER NOT NULL,
+			PRIMARY KEY (guild_id, group_id, name)
+		);
+
+		CREATE TABLE IF NOT EXISTS rollover_pending (
+			user_id INTEGER NOT NULL,
+			term TEXT NOT NULL,
+			action TEXT NOT NULL,
+			contested INTEGER NOT NULL DEFAULT 0,
+			applied INTEGER NOT NULL DEFAULT 0,
+			PRIMARY KEY (user_id, term)
+		);
+	").expect("Error creating database tables");
+
+	// Sessions from before guilds were recorded all came from the first one.
+	if add_column(conn, "mc_sessions", "guild_id", "INTEGER") {
+		conn.execute("UPDATE mc_sessions SET guild_id = ?1", [GUILDS[0].id.0]).expect("Error migrating database tables");
+	}
+}
+
+/// Add a column to a table made before it existed, returning whether it had to be added.
+fn add_column(conn: &Connection, table: &str, column: &str, decl: &str) -> bool {
+	let exists = conn
+		.prepare(&format!("SELECT 1 FROM pragma_table_info('{}') WHERE name = ?1", table)).unwrap()
+		.exists([column]).unwrap();
+	if exists {
+		return false;
+	}
+
+	conn.execute_batch(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, decl)).expect("Error migrating database tables");
+	true
+}
+
+/// Grab the shared database connection out of the context.
+pub async fn db(ctx: &Context) -> Arc<Mutex<Connection>> {
+	ctx.data.read().await.get::<Database>().cloned().expect("Database missing from the context")
+}
+
+/// Write an entry to the audit log, and mirror it to the regular log.
+pub fn audit(db: &Connection, actor: &str, user: Option<UserId>, action: &str, detail: &str) {
+	info!("Audit: {} {} {:?}: {}", actor, action, user, detail);
+
+	if db.execute(
+		"INSERT INTO audit_log (at, actor, user_id, action, detail) VALUES (?1, ?2, ?3, ?4, ?5)",
+		params![Utc::now().timestamp(), actor, user.map(|x| x.0), action, detail],
+	).is_err() {
+		error!("Error writing audit log entry for {} {}", actor, action);
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn old_sessions_get_the_first_guild() {
+		let conn = Connection::open_in_memory().unwrap();
+		conn.execute_batch("
+			CREATE TABLE mc_sessions (id TEXT PRIMARY KEY, user_id INTEGER NOT NULL, started_at INTEGER NOT NULL);
+			INSERT INTO mc_sessions (id, user_id, started_at) VALUES ('a', 1, 100);
+		").unwrap();
+
+		migrate(&conn);
+		migrate(&conn);
+
+		let guild: u64 = conn.query_row("SELECT guild_id FROM mc_sessions WHERE id = 'a'", [], |r| r.get(0)).unwrap();
+		assert_eq!(guild, GUILDS[0].id.0);
+	}
+}
\ No newline at end of file
@@ -1,93 +1,69 @@
+use std::sync::atomic::Ordering;
+
+use chrono::Utc;
 use serenity::async_trait;
 use serenity::client::{Context, EventHandler};
+use serenity::builder::{CreateApplicationCommand, CreateApplicationCommandOption, CreateApplicationCommands};
+use serenity::model::application::command::{Command, CommandOptionType};
+use serenity::model::application::component::ButtonStyle;
+use serenity::model::application::interaction::Interaction;
+use serenity::model::channel::{ChannelType, GuildChannel};
 use serenity::model::gateway::Ready;
-use serenity::model::id::ChannelId;
-use serenity::model::interactions::application_command::{ApplicationCommand, ApplicationCommandOptionType};
-use serenity::model::interactions::Interaction;
-use serenity::model::interactions::message_component::ButtonStyle;
 
 use crate::bot::Bot;
-use crate::bot::config::{GUILD_ID, SEND_INTRO};
+use crate::bot::config::{GUILDS, GuildConfig, SEND_INTRO};
+use crate::bot::mc::utils::{JOINABLE_TYPES, notify_tag_subs};
+use crate::bot::permissions::default_member_permissions;
+use crate::bot::scheduler::TASKS;
+use crate::logging::{LogContext, with_context};
 
 #[async_trait]
 impl EventHandler for Bot {
 	async fn ready(&self, ctx: Context, ready: Ready) {
 		info!("{} is connected!", ready.user.name);
+		self.last_ready.store(Utc::now().timestamp(), Ordering::Relaxed);
 
-		ApplicationCommand::set_global_application_commands(&ctx.http, |x| {
+		Command::set_global_application_commands(&ctx.http, |x| {
 			x
 		}).await.unwrap();
 
-		GUILD_ID
-			.set_application_commands(&ctx.http, |commands| {
-				commands
-					.create_application_command(|command| {
-						command
-							.name("mc")
-							.description("Launch Mission Control")
-					})
-					.create_application_command(|command| {
-						command
-							.name("become")
-							.description("Change your membership type")
-							.create_option(|option| {
-								option
-									.name("type")
-									.description("Your new membership type")
-									.kind(ApplicationCommandOptionType::String)
-									.required(true)
-									.add_string_choice("Current Member", "member")
-									.add_string_choice("Graduated Alumnus", "alumni")
-									.add_string_choice("Friend of SEDS", "friend")
-							})
-					})
-					.create_application_command(|command| {
-						command
-							.name("join")
-							.description("Join a channel")
-							.create_option(|option| {
-								option
-									.name("channel")
-									.description("The channel to join")
-									.kind(ApplicationCommandOptionType::String)
-									.required(true)
-							})
-					})
-					.create_application_command(|command| {
-						command
-							.name("leave")
-							.description("Leave a channel")
-							.create_option(|option| {
-								option
-									.name("channel")
-									.description("The channel to leave")
-									.kind(ApplicationCommandOptionType::Channel)
-									.required(true)
-							})
-				})
-			})
-			.await.unwrap();
+		for guild in GUILDS {
+			if guild.id.set_application_commands(&ctx.http, |x| create_commands(x, guild)).await.is_err() {
+				error!("Error registering commands in guild {}", guild.id);
+			}
+		}
+
+		self.scheduler.start(ctx.clone()).await;
 
 		// This block sends the message which contains the "Launch Mission Control" button.
 		// We only want to do this if the message is deleted, so guard it behind a config flag.
 		if SEND_INTRO {
-			ChannelId(869756293894783006).to_channel(&ctx).await.unwrap().guild().unwrap().send_message(&ctx, |f| {
-				f
-					.embed(|f| {
-						f.description("Click the button to launch Mission Control!")
-					})
-					.components(|g| {
-						g
-							.create_action_row(|ar| {
-								ar.create_button(|b| {
-									b
-										.label("Launch!")
-										.custom_id("launch-mc")
-										.style(ButtonStyle::Success)
+			for chan in GUILDS.iter().filter_map(|x| x.intro_channel) {
+				chan.send_message(&ctx, |f| {
+					f
+						.embed(|f| {
+							f.description("Click the button to launch Mission Control!")
+						})
+						.components(|g| {
+							g
+								.create_action_row(|ar| {
+									ar.create_button(|b| {
+										b
+											.label("Launch!")
+											.custom_id("launch-mc")
+											.style(ButtonStyle::Success)
+									})
 								})
-							})
-					})
-			}).await.unwrap();
+						})
+				}).await.unwrap();
+			}
+		}
+	}
+
+	/// Forum posts pull in everyone subscribed to one of their tags.
+	async fn thread_create(&self, ctx: Context, thread: GuildChannel) {
+		if thread.kind == ChannelType::PublicThread && !thread.applied_tags.is_empty() {
+			notify_tag_subs(&ctx, &thread).await;
 		}
 	}
 
@@ -95,9 +71,370 @@ impl EventHandler for Bot {
 	/// commands and components separately.
 	async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
 		if let Interaction::ApplicationCommand(command) = interaction {
-			self.handle_command(ctx, command).await;
+			let context = LogContext { session: None, user: Some(command.user.id.to_string()), guild: command.guild_id.map(|x| x.to_string()) };
+			with_context(context, self.handle_command(ctx, command)).await;
 		} else if let Interaction::MessageComponent(component) = interaction {
-			self.handle_component(ctx, component).await;
+			let context = LogContext { session: None, user: Some(component.user.id.to_string()), guild: component.guild_id.map(|x| x.to_string()) };
+			with_context(context, self.handle_component(ctx, component)).await;
 		}
 	}
+}
+
+/// Name a command, and hide it from anyone who can't use it in this guild.
+fn register<'a>(command: &'a mut CreateApplicationCommand, guild: &GuildConfig, name: &str) -> &'a mut CreateApplicationCommand {
+	command.name(name);
+	if let Some(perms) = default_member_permissions(guild, name) {
+		command.default_member_permissions(perms);
+	}
+	command
+}
+
+/// The project option shared by every `/project` subcommand.
+fn project_option(option: &mut CreateApplicationCommandOption) -> &mut CreateApplicationCommandOption {
+	option
+		.name("project")
+		.description("The project's role")
+		.kind(CommandOptionType::Role)
+		.required(true)
+}
+
+/// Every command we register in a guild.
+fn create_commands<'a>(commands: &'a mut CreateApplicationCommands, guild: &GuildConfig) -> &'a mut CreateApplicationCommands {
+	commands
+		.create_application_command(|command| {
+			register(command, guild, "mc")
+				.description("Launch Mission Control")
+		})
+		.create_application_command(|command| {
+			register(command, guild, "mcadmin")
+				.description("Change which roles, categories and channels Mission Control offers")
+		})
+		.create_application_command(|command| {
+			register(command, guild, "become")
+				.description("Change your membership type")
+				.create_option(|option| {
+					option
+						.name("type")
+						.description("Your new membership type")
+						.kind(CommandOptionType::String)
+						.required(true)
+						.add_string_choice("Current Member", "member")
+						.add_string_choice("Graduated Alumnus", "alumni")
+						.add_string_choice("Friend of SEDS", "friend")
+				})
+		})
+		.create_application_command(|command| {
+			register(command, guild, "join")
+				.description("Join a channel")
+				.create_option(|option| {
+					option
+						.name("channel")
+						.description("The channel to join")
+						.kind(CommandOptionType::String)
+						.required(true)
+				})
+		})
+		.create_application_command(|command| {
+			register(command, guild, "leave")
+				.description("Leave a channel")
+				.create_option(|option| {
+					option
+						.name("channel")
+						.description("The channel to leave")
+						.kind(CommandOptionType::Channel)
+						.channel_types(JOINABLE_TYPES)
+						.required(true)
+				})
+		})
+		.create_application_command(|command| {
+			register(command, guild, "project")
+				.description("Show or manage a project")
+				.create_option(|option| {
+					option
+						.name("info")
+						.description("Show a project's description, leads, meetings, channels and links")
+						.kind(CommandOptionType::SubCommand)
+						.create_sub_option(project_option)
+				})
+				.create_option(|option| {
+					option
+						.name("edit")
+						.description("Change a project's description, meeting time or links (leads only)")
+						.kind(CommandOptionType::SubCommand)
+						.create_sub_option(project_option)
+						.create_sub_option(|sub| {
+							sub
+								.name("description")
+								.description("What the project is about, or \"none\" to clear it")
+								.kind(CommandOptionType::String)
+								.required(false)
+						})
+						.create_sub_option(|sub| {
+							sub
+								.name("meeting")
+								.description("When and where the project meets, or \"none\" to clear it")
+								.kind(CommandOptionType::String)
+								.required(false)
+						})
+						.create_sub_option(|sub| {
+							sub
+								.name("links")
+								.description("Links separated by spaces, replacing the current ones, or \"none\" to clear them")
+								.kind(CommandOptionType::String)
+								.required(false)
+						})
+				})
+				.create_option(|option| {
+					option
+						.name("channel")
+						.description("Link a channel to a project, or unlink it (leads only)")
+						.kind(CommandOptionType::SubCommand)
+						.create_sub_option(project_option)
+						.create_sub_option(|sub| {
+							sub
+								.name("channel")
+								.description("A joinable channel")
+								.kind(CommandOptionType::Channel)
+								.required(true)
+						})
+						.create_sub_option(|sub| {
+							sub
+								.name("remove")
+								.description("Unlink the channel instead")
+								.kind(CommandOptionType::Boolean)
+								.required(false)
+						})
+				})
+				.create_option(|option| {
+					option
+						.name("roster")
+						.description("List a project's members (leads only)")
+						.kind(CommandOptionType::SubCommand)
+						.create_sub_option(project_option)
+				})
+				.create_option(|option| {
+					option
+						.name("add-member")
+						.description("Add someone to a project (leads only)")
+						.kind(CommandOptionType::SubCommand)
+						.create_sub_option(project_option)
+						.create_sub_option(|sub| {
+							sub
+								.name("user")
+								.description("Who to add")
+								.kind(CommandOptionType::User)
+								.required(true)
+						})
+				})
+				.create_option(|option| {
+					option
+						.name("remove-member")
+						.description("Remove someone from a project (leads only)")
+						.kind(CommandOptionType::SubCommand)
+						.create_sub_option(project_option)
+						.create_sub_option(|sub| {
+							sub
+								.name("user")
+								.description("Who to remove")
+								.kind(CommandOptionType::User)
+								.required(true)
+						})
+				})
+				.create_option(|option| {
+					option
+						.name("lead")
+						.description("Make someone a project lead, or stop them being one (officers only)")
+						.kind(CommandOptionType::SubCommand)
+						.create_sub_option(project_option)
+						.create_sub_option(|sub| {
+							sub
+								.name("user")
+								.description("The lead")
+								.kind(CommandOptionType::User)
+								.required(true)
+						})
+						.create_sub_option(|sub| {
+							sub
+								.name("remove")
+								.description("Stop them being a lead instead")
+								.kind(CommandOptionType::Boolean)
+								.required(false)
+						})
+				})
+		})
+		.create_application_command(|command| {
+			register(command, guild, "project-admin")
+				.description("Create or archive projects")
+				.create_option(|option| {
+					option
+						.name("create")
+						.description("Create a project's role and private channel")
+						.kind(CommandOptionType::SubCommand)
+						.create_sub_option(|sub| {
+							sub
+								.name("name")
+								.description("The project's name")
+								.kind(CommandOptionType::String)
+								.required(true)
+						})
+						.create_sub_option(|sub| {
+							sub
+								.name("lead")
+								.description("Who leads it")
+								.kind(CommandOptionType::User)
+								.required(false)
+						})
+						.create_sub_option(|sub| {
+							sub
+								.name("category")
+								.description("The category to put its channel in")
+								.kind(CommandOptionType::Channel)
+								.channel_types(&[ChannelType::Category])
+								.required(false)
+						})
+				})
+				.create_option(|option| {
+					option
+						.name("archive")
+						.description("Save a project's roster, archive its channels and take its role from everyone")
+						.kind(CommandOptionType::SubCommand)
+						.create_sub_option(project_option)
+						.create_sub_option(|sub| {
+							sub
+								.name("category")
+								.description("The category to move its channels to (defaults to the archive)")
+								.kind(CommandOptionType::Channel)
+								.channel_types(&[ChannelType::Category])
+								.required(false)
+						})
+				})
+		})
+		.create_application_command(|command| {
+			register(command, guild, "migrate-access")
+				.description("Convert member overwrites on role-access channels into access roles")
+		})
+		.create_application_command(|command| {
+			register(command, guild, "reconcile")
+				.description("Report overwrites and roles that don't match what Mission Control recorded")
+				.create_option(|option| {
+					option
+						.name("update_records")
+						.description("Record untracked grants and forget vanished ones, without changing Discord")
+						.kind(CommandOptionType::Boolean)
+						.required(false)
+				})
+		})
+		.create_application_command(|command| {
+			register(command, guild, "import")
+				.description("Promote everyone on a CSV roster of paid members to Member")
+				.create_option(|option| {
+					option
+						.name("roster")
+						.description("A CSV with a discord column of usernames or IDs")
+						.kind(CommandOptionType::Attachment)
+						.required(true)
+				})
+				.create_option(|option| {
+					option
+						.name("term")
+						.description("The term these dues are for, like \"Fall 2026\" (defaults to the current one)")
+						.kind(CommandOptionType::String)
+						.required(false)
+				})
+				.create_option(|option| {
+					option
+						.name("dry_run")
+						.description("Only preview the changes")
+						.kind(CommandOptionType::Boolean)
+						.required(false)
+				})
+		})
+		.create_application_command(|command| {
+			register(command, guild, "export")
+				.description("Export every member's membership, projects, roles and channels")
+				.create_option(|option| {
+					option
+						.name("format")
+						.description("The file format (defaults to CSV)")
+						.kind(CommandOptionType::String)
+						.required(false)
+						.add_string_choice("CSV", "csv")
+						.add_string_choice("JSON", "json")
+				})
+				.create_option(|option| {
+					option
+						.name("project")
+						.description("Only export members of this project")
+						.kind(CommandOptionType::Role)
+						.required(false)
+				})
+		})
+		.create_application_command(|command| {
+			register(command, guild, "stats")
+				.description("Show membership, project, channel and Mission Control usage statistics")
+				.create_option(|option| {
+					option
+						.name("csv")
+						.description("Also attach every number as a CSV")
+						.kind(CommandOptionType::Boolean)
+						.required(false)
+				})
+		})
+		.create_application_command(|command| {
+			register(command, guild, "jobs")
+				.description("Inspect and trigger background jobs")
+				.create_option(|option| {
+					option
+						.name("list")
+						.description("List every job and when it next runs")
+						.kind(CommandOptionType::SubCommand)
+				})
+				.create_option(|option| {
+					option
+						.name("run")
+						.description("Run a task now")
+						.kind(CommandOptionType::SubCommand)
+						.create_sub_option(|sub| {
+							sub
+								.name("task")
+								.description("The task to run")
+								.kind(CommandOptionType::String)
+								.required(true);
+							for task in TASKS {
+								sub.add_string_choice(task, task);
+							}
+							sub
+						})
+				})
+		})
+		.create_application_command(|command| {
+			register(command, guild, "rollover")
+				.description("Settle membership rollovers that members contested")
+				.create_option(|option| {
+					option
+						.name("contested")
+						.description("List contested rollovers waiting on an officer")
+						.kind(CommandOptionType::SubCommand)
+				})
+				.create_option(|option| {
+					option
+						.name("resolve")
+						.description("Apply or dismiss a member's contested rollover")
+						.kind(CommandOptionType::SubCommand)
+						.create_sub_option(|sub| {
+							sub
+								.name("member")
+								.description("Who contested it")
+								.kind(CommandOptionType::User)
+								.required(true)
+						})
+						.create_sub_option(|sub| {
+							sub
+								.name("apply")
+								.description("Make the change after all, instead of letting them keep their membership")
+								.kind(CommandOptionType::Boolean)
+								.required(true)
+						})
+				})
+	})
 }
\ No newline at end of file
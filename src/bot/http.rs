@@ -0,0 +1,121 @@
+use std::convert::Infallible;
+use std::env;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::Ordering;
+
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use hyper::header::CONTENT_TYPE;
+use hyper::service::{make_service_fn, service_fn};
+use rusqlite::Connection;
+use serde_json::json;
+use serenity::client::bridge::gateway::ShardManager;
+use serenity::gateway::ConnectionStage;
+
+use crate::bot::Bot;
+use crate::bot::metrics;
+
+/// Where we serve our endpoints unless `HTTP_ADDR` says otherwise. Set `HTTP_ADDR` empty to turn
+/// the server off.
+pub const DEFAULT_ADDR: &str = "127.0.0.1:9090";
+
+/// Everything the health endpoints look at.
+pub struct HttpState {
+	pub bot: Arc<Bot>,
+	pub shard_manager: Arc<tokio::sync::Mutex<ShardManager>>,
+	pub db: Arc<Mutex<Connection>>,
+}
+
+/// Check the gateway and database. Healthy means every shard is connected and the database
+/// answers; ready also needs `ready` to have fired, so our commands are registered.
+async fn health(state: &HttpState) -> (bool, bool, String) {
+	let shards: Vec<_> = {
+		let manager = state.shard_manager.lock().await;
+		let runners = manager.runners.lock().await;
+		runners.iter().map(|(id, info)| (id.0, info.stage, info.latency)).collect()
+	};
+
+	let connected = !shards.is_empty() && shards.iter().all(|(_, stage, _)| *stage == ConnectionStage::Connected);
+	let db_ok = state.db.lock().unwrap().query_row("SELECT 1", [], |_| Ok(())).is_ok();
+	let last_ready = match state.bot.last_ready.load(Ordering::Relaxed) {
+		0 => None,
+		ts => Some(ts),
+	};
+
+	let healthy = connected && db_ok;
+	let ready = healthy && last_ready.is_some();
+
+	let report = json!({
+		"healthy": healthy,
+		"ready": ready,
+		"shards": shards.iter().map(|(id, stage, latency)| json!({
+			"id": id,
+			"stage": stage.to_string(),
+			"latency_ms": latency.map(|x| x.as_millis() as u64),
+		})).collect::<Vec<_>>(),
+		"last_ready": last_ready,
+		"database": db_ok,
+	});
+
+	(healthy, ready, report.to_string())
+}
+
+async fn handle(state: Arc<HttpState>, req: Request<Body>) -> Result<Response<Body>, Infallible> {
+	let resp = match (req.method(), req.uri().path()) {
+		(&Method::GET, "/metrics") => Response::builder()
+			.header(CONTENT_TYPE, "text/plain; version=0.0.4")
+			.body(Body::from(metrics::render())),
+		(&Method::GET, path @ ("/healthz" | "/readyz")) => {
+			let (healthy, ready, report) = health(&state).await;
+			let ok = if path == "/healthz" { healthy } else { ready };
+
+			Response::builder()
+				.status(if ok { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE })
+				.header(CONTENT_TYPE, "application/json")
+				.body(Body::from(report))
+		}
+		_ => Response::builder()
+			.status(StatusCode::NOT_FOUND)
+			.body(Body::from("Not found")),
+	};
+
+	Ok(resp.unwrap())
+}
+
+/// Serve our HTTP endpoints until the process exits.
+pub async fn serve(state: HttpState) {
+	let addr = env::var("HTTP_ADDR").unwrap_or_else(|_| DEFAULT_ADDR.to_string());
+	if addr.is_empty() {
+		return;
+	}
+
+	let addr: SocketAddr = match addr.parse() {
+		Ok(addr) => addr,
+		Err(_) => {
+			error!("HTTP_ADDR {} is not a valid address", addr);
+			return;
+		}
+	};
+
+	let server = match Server::try_bind(&addr) {
+		Ok(server) => server,
+		Err(why) => {
+			error!("Error binding HTTP server to {}: {}", addr, why);
+			return;
+		}
+	};
+
+	info!("Serving metrics and health checks on http://{}", addr);
+
+	let state = Arc::new(state);
+	let service = make_service_fn(move |_| {
+		let state = state.clone();
+		async move {
+			Ok::<_, Infallible>(service_fn(move |req| handle(state.clone(), req)))
+		}
+	});
+
+	if let Err(why) = server.serve(service).await {
+		error!("HTTP server error: {}", why);
+	}
+}
\ No newline at end of file
@@ -0,0 +1,178 @@
+use chrono::Utc;
+use serenity::client::Context;
+use serenity::model::channel::{ChannelType, GuildChannel};
+use serenity::model::id::{ChannelId, GuildId};
+
+use crate::bot::config::{GUILDS, GuildConfig, INACTIVE_REPORT_DAYS};
+use crate::bot::db::{audit, db};
+use crate::bot::mc::utils::chan_joinable;
+use crate::bot::metrics;
+use crate::bot::scheduler::discord_time;
+use crate::bot::settings::{self, Setting};
+
+/// How many inactive channels a report lists before summing up the rest.
+const REPORT_MAX: usize = 30;
+
+const DAY: i64 = 24 * 60 * 60;
+
+/// Look for listed channels nobody has posted in lately, archive the ones past each guild's
+/// `auto_archive_days`, and report them all to the officers. The scheduler runs it weekly. Given a
+/// guild, only that one is scanned.
+pub async fn scan(ctx: &Context, only: Option<GuildId>) {
+	for guild in GUILDS.iter().filter(|x| only.map(|id| id == x.id).unwrap_or(true)) {
+		scan_guild(ctx, guild).await;
+	}
+}
+
+/// When something was last posted in a channel, or when it was made if nothing has been.
+fn last_active(channel: &GuildChannel) -> i64 {
+	channel.last_message_id
+		.map(|x| x.created_at())
+		.unwrap_or_else(|| channel.id.created_at())
+		.unix_timestamp()
+}
+
+/// Has it been at least `days` days since `at`?
+fn inactive_for(now: i64, at: i64, days: i64) -> bool {
+	now - at >= days * DAY
+}
+
+/// Which channels to report and which to archive, from when each was last active, both oldest
+/// first. Channels can be archived sooner than they're reported, so archived ones are reported too.
+fn select(last_active: &[(ChannelId, i64)], now: i64, archive_days: Option<i64>) -> (Vec<(ChannelId, i64)>, Vec<ChannelId>) {
+	let mut last_active = last_active.to_vec();
+	last_active.sort_by_key(|(_, at)| *at);
+
+	let archive: Vec<ChannelId> = match archive_days {
+		Some(days) => last_active.iter().filter(|(_, at)| inactive_for(now, *at, days)).map(|(x, _)| *x).collect(),
+		None => vec![],
+	};
+
+	let report = last_active.into_iter()
+		.filter(|(x, at)| inactive_for(now, *at, INACTIVE_REPORT_DAYS) || archive.contains(x))
+		.collect();
+
+	(report, archive)
+}
+
+async fn scan_guild(ctx: &Context, guild: &GuildConfig) {
+	let chans = match guild.id.channels(&ctx.http).await {
+		Ok(chans) => chans,
+		Err(_) => {
+			error!("Error retrieving channels to scan for inactivity in guild {}", guild.id);
+			metrics::discord_error("get_channels");
+			return;
+		}
+	};
+
+	// Voice channels don't say when they were last used, so only channels people post in count.
+	let last_active: Vec<_> = chans.values()
+		.filter(|x| matches!(x.kind, ChannelType::Text | ChannelType::Forum) && chan_joinable(x))
+		.map(|x| (x.id, last_active(x)))
+		.collect();
+
+	let archive_days = match (guild.auto_archive_days, guild.archive_category) {
+		(Some(_), None) => {
+			warn!("Guild {} archives inactive channels, but has no archive category", guild.id);
+			None
+		}
+		(days, _) => days,
+	};
+
+	let (inactive, to_archive) = select(&last_active, Utc::now().timestamp(), archive_days);
+	if inactive.is_empty() {
+		return;
+	}
+
+	info!("Found {} inactive channels in guild {}", inactive.len(), guild.id);
+
+	let name = |id: &ChannelId| chans.get(id).map(|x| x.name.clone()).unwrap_or_else(|| id.to_string());
+
+	// Nothing is picked for archiving without an archive category to move it to.
+	let mut archived = vec![];
+	if let Some(category) = guild.archive_category.filter(|_| !to_archive.is_empty()) {
+		for chan in &to_archive {
+			if chan.edit(&ctx.http, |c| c.category(category)).await.is_err() {
+				error!("Error moving inactive channel {} to the archive", name(chan));
+				metrics::discord_error("edit_channel");
+				continue;
+			}
+
+			archived.push(*chan);
+		}
+
+		// Excluding them keeps them out of every menu, and officers can undo it from /mcadmin.
+		let db = db(ctx).await;
+		let db = db.lock().unwrap();
+		for (chan, at) in inactive.iter().filter(|(x, _)| archived.contains(x)) {
+			if !settings::set(&db, guild.id, &Setting::Excluded.key(guild), chan.0, true) {
+				error!("Error excluding archived channel {}", name(chan));
+			}
+			audit(&db, "inactive-channels", None, "channel-archive", &format!("{}: last active {}", name(chan), at));
+		}
+	}
+
+	let chan = match guild.officer_channel {
+		Some(chan) => chan,
+		None => return,
+	};
+
+	let mut report = format!("**Inactive channels** (nothing posted in {}+ days):\n", INACTIVE_REPORT_DAYS);
+	for (x, at) in inactive.iter().take(REPORT_MAX) {
+		let note = if archived.contains(x) { " (archived)" } else { "" };
+		report.push_str(&format!("- <#{}>: last active {}{}\n", x, discord_time(*at), note));
+	}
+	if inactive.len() > REPORT_MAX {
+		report.push_str(&format!("...and {} more\n", inactive.len() - REPORT_MAX));
+	}
+	if !archived.is_empty() {
+		report.push_str(&format!("Archived {} channels inactive for over {} days.", archived.len(), guild.auto_archive_days.unwrap()));
+	}
+
+	if chan.say(&ctx.http, report).await.is_err() {
+		error!("Error sending the inactive channel report to channel {}", chan);
+		metrics::discord_error("send_message");
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn inactive_cutoff() {
+		let now = 1_000 * DAY;
+
+		assert!(!inactive_for(now, now, INACTIVE_REPORT_DAYS));
+		assert!(!inactive_for(now, now - INACTIVE_REPORT_DAYS * DAY + 1, INACTIVE_REPORT_DAYS));
+		assert!(inactive_for(now, now - INACTIVE_REPORT_DAYS * DAY, INACTIVE_REPORT_DAYS));
+		assert!(inactive_for(now, 0, INACTIVE_REPORT_DAYS));
+	}
+
+	#[test]
+	fn archives_before_the_report_cutoff() {
+		let now = 1_000 * DAY;
+		let chans = [
+			(ChannelId(1), now - 10 * DAY),
+			(ChannelId(2), now - 40 * DAY),
+			(ChannelId(3), now - (INACTIVE_REPORT_DAYS + 1) * DAY),
+		];
+
+		let (report, archive) = select(&chans, now, Some(30));
+		assert_eq!(archive, vec![ChannelId(3), ChannelId(2)]);
+		assert_eq!(report.iter().map(|(x, _)| *x).collect::<Vec<_>>(), vec![ChannelId(3), ChannelId(2)]);
+	}
+
+	#[test]
+	fn reports_without_archiving() {
+		let now = 1_000 * DAY;
+		let chans = [
+			(ChannelId(1), now - 10 * DAY),
+			(ChannelId(2), now - (INACTIVE_REPORT_DAYS + 1) * DAY),
+		];
+
+		let (report, archive) = select(&chans, now, None);
+		assert!(archive.is_empty());
+		assert_eq!(report.iter().map(|(x, _)| *x).collect::<Vec<_>>(), vec![ChannelId(2)]);
+	}
+}
\ No newline at end of file
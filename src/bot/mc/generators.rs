@@ -1,24 +1,42 @@
-use serenity::builder::CreateInteractionResponseData;
-use serenity::model::interactions::InteractionApplicationCommandCallbackDataFlags;
-use serenity::model::interactions::message_component::ButtonStyle;
-use crate::bot::config::MAX_LIST_SIZE;
+use serenity::builder::{CreateActionRow, CreateComponents, CreateInteractionResponse, CreateInteractionResponseData};
+use serenity::model::application::component::{ButtonStyle, InputTextStyle};
+use serenity::model::application::interaction::{InteractionResponseType, MessageFlags};
+use serenity::model::channel::ReactionType;
+
+use crate::bot::config::{CHANNEL_REQUEST_THRESHOLD, MAX_LIST_SIZE};
 
 use crate::bot::mc::{MC, Modifications, State, StateProgress};
+use crate::bot::settings::Setting;
 
 impl MC {
 	pub fn generate_main_menu<'a, 'b>(&self, d: &'a mut CreateInteractionResponseData<'b>) -> &'a mut CreateInteractionResponseData<'b> {
-		d.flags(InteractionApplicationCommandCallbackDataFlags::EPHEMERAL);
+		d.flags(MessageFlags::EPHEMERAL);
+
+		// Admins get a button per setting instead of per group.
+		let ids: Vec<_> = if self.admin {
+			d.content("Pick a setting to change. Changes take effect immediately.");
+
+			Setting::all(self.guild).iter().map(|x| (x.key(self.guild), x.name(self.guild))).collect()
+		} else {
+			d.content(self.notice.as_deref().unwrap_or_default());
+
+			std::iter::once(("membership".to_string(), "Membership".to_string()))
+				.chain(self.guild.assignables.iter().map(|x| (x.id.to_string(), x.name.to_string())))
+				.collect()
+		};
 
 		d.components(|c| {
+			// Discord only allows five buttons per row, so chunk them.
+			for row in ids.chunks(5) {
+				c.create_action_row(|ar| {
+					for (id, name) in row {
+						ar.create_button(|b| { b.custom_id(id).label(name).style(ButtonStyle::Primary) });
+					}
+					ar
+				});
+			}
+
 			c.create_action_row(|ar| {
-				ar
-					.create_button(|b| { b.custom_id("membership").label("Membership").style(ButtonStyle::Primary) })
-					.create_button(|b| { b.custom_id("roles").label("Roles").style(ButtonStyle::Primary) })
-					.create_button(|b| { b.custom_id("chans").label("Channels").style(ButtonStyle::Primary) })
-					.create_button(|b| { b.custom_id("projs").label("Projects").style(ButtonStyle::Primary) })
-					.create_button(|b| { b.custom_id("games").label("Games").style(ButtonStyle::Primary) })
-			})
-			.create_action_row(|ar| {
 				ar.create_button(|b| { b.custom_id("exit-mc").label("Done").style(ButtonStyle::Secondary) })
 			})
 		})
@@ -32,71 +50,98 @@ impl MC {
 	}
 
 	pub fn generate_modification<'a, 'b>(&self, d: &'a mut CreateInteractionResponseData<'b>) -> &'a mut CreateInteractionResponseData<'b> {
+		// Admin menus show their setting instead.
+		if !self.admin {
+			d.content(self.notice.as_deref().unwrap_or_default());
+		}
+
 		match &self.state {
 			State::Modification(state) => {
 				match state {
 					StateProgress::Initial => {
+						let (add, remove, requestable) = match self.modification.unwrap() {
+							Modifications::Setting(setting) => {
+								d.content(self.setting_summary(setting));
+								("Add", "Remove", false)
+							}
+							modif => {
+								let group = modif.assignable(self.guild).unwrap();
+								(group.add_label, group.remove_label, group.requestable())
+							}
+						};
+
 						d.components(|c| {
 							c.create_action_row(|ar| {
-								match self.modification.as_ref().unwrap() {
-									Modifications::Membership => unreachable!(),
-									Modifications::Roles => {
-										ar
-											.create_button(|b| { b.custom_id("add").label("Add Roles").style(ButtonStyle::Success) })
-											.create_button(|b| { b.custom_id("remove").label("Remove Roles").style(ButtonStyle::Danger) })
-											.create_button(|b| { b.custom_id("done").label("Done").style(ButtonStyle::Secondary) })
-									}
-									Modifications::Channels => {
-										ar
-											.create_button(|b| { b.custom_id("add").label("Join Channels").style(ButtonStyle::Success) })
-											.create_button(|b| { b.custom_id("remove").label("Leave Channels").style(ButtonStyle::Danger) })
-											.create_button(|b| { b.custom_id("done").label("Done").style(ButtonStyle::Secondary) })
-									}
-									Modifications::Projects => {
-										ar
-											.create_button(|b| { b.custom_id("add").label("Join Projects").style(ButtonStyle::Success) })
-											.create_button(|b| { b.custom_id("remove").label("Leave Projects").style(ButtonStyle::Danger) })
-											.create_button(|b| { b.custom_id("done").label("Done").style(ButtonStyle::Secondary) })
-									}
-									Modifications::Games => {
-										ar
-											.create_button(|b| { b.custom_id("add").label("Add Games").style(ButtonStyle::Success) })
-											.create_button(|b| { b.custom_id("remove").label("Remove Games").style(ButtonStyle::Danger) })
-											.create_button(|b| { b.custom_id("done").label("Done").style(ButtonStyle::Secondary) })
-									}
+								ar
+									.create_button(|b| { b.custom_id("add").label(add).style(ButtonStyle::Success) })
+									.create_button(|b| { b.custom_id("remove").label(remove).style(ButtonStyle::Danger) });
+								if requestable {
+									ar.create_button(|b| { b.custom_id("request").label("Request New").style(ButtonStyle::Primary) });
 								}
+								ar.create_button(|b| { b.custom_id("done").label("Done").style(ButtonStyle::Secondary) })
 							})
 						})
 					}
 					StateProgress::Add => {
-						if let Some(modif) = self.modification {
-							let placeholder = match modif {
-								Modifications::Roles => "Select a role to add...",
-								Modifications::Channels => "Select a channel to join...",
-								Modifications::Projects => "Select a project to join...",
-								Modifications::Games => "Select a game to add...",
-								Modifications::Membership => unreachable!(),
-							};
-
-							sel_menu(d, placeholder, self.list.as_ref(), self.page)
-						} else {
-							unreachable!()
-						}
+						let placeholder = match self.modification.unwrap() {
+							Modifications::Setting(setting) => {
+								d.content(self.setting_summary(setting));
+								setting.placeholders().0
+							}
+							modif => modif.assignable(self.guild).unwrap().add_placeholder,
+						};
+
+						sel_menu(d, placeholder, self.list.as_ref(), self.page)
 					}
 					StateProgress::Remove => {
-						if let Some(modif) = self.modification {
-							let placeholder = match modif {
-								Modifications::Roles => "Select a role to remove...",
-								Modifications::Channels => "Select a channel to leave...",
-								Modifications::Projects => "Select a project to leave...",
-								Modifications::Games => "Select a game to remove...",
-								Modifications::Membership => unreachable!(),
-							};
-
-							sel_menu(d, placeholder, self.list.as_ref(), self.page)
+						let placeholder = match self.modification.unwrap() {
+							Modifications::Setting(setting) => {
+								d.content(self.setting_summary(setting));
+								setting.placeholders().1
+							}
+							modif => modif.assignable(self.guild).unwrap().remove_placeholder,
+						};
+
+						sel_menu(d, placeholder, self.list.as_ref(), self.page)
+					}
+					StateProgress::Confirm => {
+						let pending = self.pending.as_ref().unwrap();
+						let chans: Vec<_> = pending.channels.iter().map(|x| format!("<#{}>", x)).collect();
+
+						if pending.join {
+							d.content(format!("Joining <@&{}> will also add you to {}.", pending.project, chans.join(", ")));
 						} else {
-							unreachable!()
+							d.content(format!("You're in {} through <@&{}>. Leave them too?", chans.join(", "), pending.project));
+						}
+
+						d.components(|c| {
+							c.create_action_row(|ar| {
+								if pending.join {
+									ar.create_button(|b| { b.custom_id("confirm").label("Join").style(ButtonStyle::Success) });
+								} else {
+									ar
+										.create_button(|b| { b.custom_id("confirm").label("Leave Both").style(ButtonStyle::Danger) })
+										.create_button(|b| { b.custom_id("confirm-only").label("Leave Project Only").style(ButtonStyle::Primary) });
+								}
+								ar.create_button(|b| { b.custom_id("cancel").label("Cancel").style(ButtonStyle::Secondary) })
+							})
+						})
+					}
+					StateProgress::Request => {
+						if self.notice.is_none() {
+							d.content(format!("Vote for a channel someone's asked for, or request a new one. Once {} people ask for the same one, it's created and they're all added.", CHANNEL_REQUEST_THRESHOLD));
 						}
+
+						let (shown, page, pages) = paged(self.list.as_ref(), self.page);
+
+						d.components(|c| {
+							sel_row(c, "Select a request to vote for...", shown);
+							c.create_action_row(|ar| {
+								page_buttons(ar, page, pages)
+									.create_button(|b| { b.custom_id("new-request").label("Request New").style(ButtonStyle::Primary) })
+									.create_button(|b| { b.custom_id("done").label("Done").style(ButtonStyle::Secondary) })
+							})
+						})
 					}
 					StateProgress::Change => {
 						if let Some(modif) = self.modification {
@@ -114,54 +159,152 @@ impl MC {
 			_ => unreachable!()
 		}
 	}
+
+	/// The form asking for the name of a new channel to request.
+	pub fn generate_request_modal<'a, 'b>(&self, r: &'a mut CreateInteractionResponse<'b>) -> &'a mut CreateInteractionResponse<'b> {
+		r.kind(InteractionResponseType::Modal);
+		r.interaction_response_data(|d| {
+			d.custom_id("request-form").title("Request a New Channel");
+			d.components(|c| {
+				c.create_action_row(|ar| {
+					ar.create_input_text(|t| {
+						t
+							.custom_id("name")
+							.label("Name")
+							.placeholder("What's it for?")
+							.style(InputTextStyle::Short)
+							.min_length(1)
+							.max_length(50)
+							.required(true)
+					})
+				})
+			})
+		})
+	}
+
+	/// What a setting currently holds, shown above its menus.
+	fn setting_summary(&self, setting: Setting) -> String {
+		let values: Vec<_> = setting.values(self.guild).into_iter().map(|x| setting.mention(x)).collect();
+		let values = if values.is_empty() { "None".to_string() } else { values.join(", ") };
+
+		format!("**{}:** {}", setting.name(self.guild), values)
+	}
 }
 
 pub struct MenuOption {
 	pub label: String,
 	pub val: String,
+	/// Shown before the label, e.g. to tell text and voice channels apart.
+	pub emoji: Option<&'static str>,
+	/// Shown under the label; cut down to Discord's limit.
+	pub description: Option<String>,
+}
+
+/// How long a select menu option's description can be.
+const MAX_DESCRIPTION: usize = 100;
+
+/// Cut a string down to at most `max` characters, marking that it was cut.
+fn truncate(s: &str, max: usize) -> String {
+	if s.chars().count() <= max {
+		return s.to_string();
+	}
+
+	let mut out: String = s.chars().take(max - 1).collect();
+	out.push('…');
+	out
 }
 
-fn sel_menu<'a, 'b>(d: &'a mut CreateInteractionResponseData<'b>, placehold: &str, list: &Vec<MenuOption>, _page: u8) -> &'a mut CreateInteractionResponseData<'b> {
-	if list.len() > MAX_LIST_SIZE {
-		error!("Warning! Trying to display a list near the maximum of 25! Time to implement paging...")
+/// The options on one page of a list, the page they're really on, and how many pages there are.
+/// A select menu holds at most 25 options, so longer lists are split into pages. The list can
+/// shrink under us, so stay on the last page if it's now past the end.
+fn paged(list: &[MenuOption], page: u8) -> (&[MenuOption], usize, usize) {
+	let pages = list.len().div_ceil(MAX_LIST_SIZE).max(1);
+	let page = (page as usize).min(pages - 1);
+	(&list[page * MAX_LIST_SIZE..list.len().min((page + 1) * MAX_LIST_SIZE)], page, pages)
+}
+
+/// Add buttons to move between pages, if there's more than one.
+fn page_buttons(ar: &mut CreateActionRow, page: usize, pages: usize) -> &mut CreateActionRow {
+	if pages > 1 {
+		ar
+			.create_button(|b| { b.custom_id("prev-page").label("Previous Page").style(ButtonStyle::Primary).disabled(page == 0) })
+			.create_button(|b| { b.custom_id("next-page").label("Next Page").style(ButtonStyle::Primary).disabled(page + 1 >= pages) });
 	}
+	ar
+}
+
+fn sel_menu<'a, 'b>(d: &'a mut CreateInteractionResponseData<'b>, placehold: &str, list: &[MenuOption], page: u8) -> &'a mut CreateInteractionResponseData<'b> {
+	let (shown, page, pages) = paged(list, page);
 
 	d.components(|c| {
-		c.create_action_row(|ar| {
-			ar.create_select_menu(|sm| {
-				sm.custom_id("sel-val");
-				if list.is_empty() {
-					sm
-						.disabled(true)
-						.placeholder("There are no available options!")
-						.options(|smo| smo.create_option(|o| o.label("none").value("none")))
-				} else {
-					sm.placeholder(placehold);
-					sm.options(|smo| {
-						for li in list {
-							smo.create_option(|o| o.label(li.label.as_str()).value(li.val.as_str()));
-						}
-						smo
-					})
-				}
-			})
-		})
+		sel_row(c, placehold, shown)
 			.create_action_row(|ar| {
-				ar
-					// .create_button(|b| {
-					// 	b.custom_id("prev-page").label("Previous Page").style(ButtonStyle::Primary);
-					//
-					// 	if page == 0 {
-					// 		b.disabled(true);
-					// 	}
-					//
-					// 	b
-					// })
-					// .create_button(|b| {
-					// 	b.custom_id("next-page").label("Next Page").style(ButtonStyle::Primary);
-					// 	b
-					// })
+				page_buttons(ar, page, pages)
 					.create_button(|b| { b.custom_id("done").label("Done").style(ButtonStyle::Secondary) })
 			})
 	})
+}
+
+/// The row holding a select menu of `list`, or a disabled one if it's empty.
+fn sel_row<'a>(c: &'a mut CreateComponents, placehold: &str, list: &[MenuOption]) -> &'a mut CreateComponents {
+	c.create_action_row(|ar| {
+		ar.create_select_menu(|sm| {
+			sm.custom_id("sel-val");
+			if list.is_empty() {
+				sm
+					.disabled(true)
+					.placeholder("There are no available options!")
+					.options(|smo| smo.create_option(|o| o.label("none").value("none")))
+			} else {
+				sm.placeholder(placehold);
+				sm.options(|smo| {
+					for li in list {
+						smo.create_option(|o| {
+							o.label(li.label.as_str()).value(li.val.as_str());
+							if let Some(emoji) = li.emoji {
+								o.emoji(ReactionType::Unicode(emoji.to_string()));
+							}
+							if let Some(description) = &li.description {
+								o.description(truncate(description, MAX_DESCRIPTION));
+							}
+							o
+						});
+					}
+					smo
+				})
+			}
+		})
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn options(n: usize) -> Vec<MenuOption> {
+		(0..n).map(|x| MenuOption { label: x.to_string(), val: x.to_string(), emoji: None, description: None }).collect()
+	}
+
+	#[test]
+	fn pages_cover_every_option() {
+		let list = options(MAX_LIST_SIZE * 2 + 1);
+
+		let (page, idx, pages) = paged(&list, 1);
+		assert_eq!((page.len(), idx, pages), (MAX_LIST_SIZE, 1, 3));
+		assert_eq!(page[0].val, MAX_LIST_SIZE.to_string());
+
+		let (page, idx, _) = paged(&list, 2);
+		assert_eq!((page.len(), idx), (1, 2));
+	}
+
+	#[test]
+	fn pages_clamp_to_the_last() {
+		// A list can shrink under the user, e.g. after leaving the last channel on a page.
+		let list = options(MAX_LIST_SIZE + 1);
+		let (page, idx, pages) = paged(&list, 5);
+		assert_eq!((page.len(), idx, pages), (1, 1, 2));
+
+		let (page, idx, pages) = paged(&[], 0);
+		assert_eq!((page.len(), idx, pages), (0, 0, 1));
+	}
 }
\ No newline at end of file
@@ -1,27 +1,31 @@
 use std::sync::Arc;
 
-use serenity::model::interactions::message_component::MessageComponentInteraction;
+use serenity::model::application::interaction::message_component::MessageComponentInteraction;
 
 use crate::bot::mc::{MC, Modifications, State, StateProgress};
+use crate::bot::settings::Setting;
 
 impl MC {
 	pub fn handle_main_menu(&mut self, a: Arc<MessageComponentInteraction>) {
 		self.state = State::Modification(StateProgress::Initial);
+		self.page = 0;
 
 		match a.data.custom_id.as_str() {
+			"exit-mc" => {
+				self.state = State::Done;
+				self.running = false;
+			}
+			id if self.admin => {
+				self.modification = Some(Modifications::Setting(Setting::from_key(self.guild, id).unwrap()));
+			}
 			"membership" => {
 				self.modification = Some(Modifications::Membership);
 				self.state = State::Modification(StateProgress::Change);
 			}
-			"roles" => self.modification = Some(Modifications::Roles),
-			"chans" => self.modification = Some(Modifications::Channels),
-			"projs" => self.modification = Some(Modifications::Projects),
-			"games" => self.modification = Some(Modifications::Games),
-			"exit-mc" => {
-				self.state = State::Done;
-				self.running = false;
+			id => {
+				let idx = self.guild.assignables.iter().position(|x| x.id == id).unwrap();
+				self.modification = Some(Modifications::Group(idx));
 			}
-			_ => unreachable!()
 		}
 	}
 
@@ -35,8 +39,18 @@ impl MC {
 				match state {
 					StateProgress::Initial => {
 						match a.data.custom_id.as_str() {
-							"add" => self.state = State::Modification(StateProgress::Add),
-							"remove" => self.state = State::Modification(StateProgress::Remove),
+							"add" => {
+								self.state = State::Modification(StateProgress::Add);
+								self.page = 0;
+							}
+							"remove" => {
+								self.state = State::Modification(StateProgress::Remove);
+								self.page = 0;
+							}
+							"request" => {
+								self.state = State::Modification(StateProgress::Request);
+								self.page = 0;
+							}
 							"done" => {
 								self.state = State::MainMenu;
 								self.modification = None;
@@ -72,8 +86,42 @@ impl MC {
 							_ => unreachable!()
 						}
 					}
+					StateProgress::Confirm => {
+						let pending = self.pending.as_mut().unwrap();
+
+						match a.data.custom_id.as_str() {
+							"confirm" | "confirm-only" => {
+								pending.with_channels = a.data.custom_id == "confirm";
+								self.value = Some(pending.project.to_string());
+							}
+							"cancel" => {
+								let back = if pending.join { StateProgress::Add } else { StateProgress::Remove };
+								self.pending = None;
+								self.state = State::Modification(back);
+							}
+							_ => unreachable!()
+						}
+					}
+					StateProgress::Request => {
+						match a.data.custom_id.as_str() {
+							"next-page" => self.page += 1,
+							"prev-page" => self.page = self.page.saturating_sub(1),
+							"done" => {
+								self.state = State::MainMenu;
+								self.modification = None;
+							}
+							"sel-val" => {
+								self.value = a.data.values.first().cloned();
+							}
+							// The run loop answers with the request form, and we get its value when it's submitted.
+							"new-request" => self.modal_open = true,
+							_ => unreachable!()
+						}
+					}
 					StateProgress::Change => {
 						match a.data.custom_id.as_str() {
+							"next-page" => self.page += 1,
+							"prev-page" => self.page = self.page.saturating_sub(1),
 							"done" => {
 								self.state = State::MainMenu;
 								self.modification = None;
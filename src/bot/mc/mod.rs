@@ -1,19 +1,38 @@
 use std::sync::Arc;
 use std::time::Duration;
 
-use serenity::builder::{CreateInteractionResponse, CreateInteractionResponseData};
+use chrono::Utc;
+use rusqlite::params;
+use serenity::builder::{CreateInteractionResponse, CreateInteractionResponseData, EditInteractionResponse};
 use serenity::client::Context;
+use serenity::model::application::interaction::application_command::ApplicationCommandInteraction;
+use serenity::json::{Value, hashmap_to_json_map};
+use serenity::model::application::interaction::{InteractionResponseType, MessageFlags};
+use serenity::model::application::interaction::message_component::MessageComponentInteraction;
+use serenity::model::application::interaction::modal::ModalSubmitInteraction;
+use serenity::model::application::component::ActionRowComponent;
 use serenity::model::channel::Message;
-use serenity::model::interactions::InteractionResponseType;
-use serenity::model::interactions::message_component::MessageComponentInteraction;
-use serenity::model::prelude::application_command::ApplicationCommandInteraction;
+use serenity::model::id::{ChannelId, RoleId};
 use serenity::model::user::User;
+use tokio::sync::watch;
 
+use crate::bot::config::{Assignable, GuildConfig};
+use crate::bot::db::db;
 use crate::bot::mc::generators::MenuOption;
+use crate::bot::metrics;
+use crate::bot::settings::Setting;
+use crate::logging::{LogContext, context, with_context};
+
+/// What open menus are changed to when the bot shuts down.
+const RESTARTING: &str = "Mission Control is restarting. Launch it again in a minute!";
+
+/// How long a shutdown waits for open menus to be closed.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(10);
 
 mod handlers;
 mod processor;
 mod generators;
+mod ratelimit;
 pub mod utils;
 
 #[derive(Copy, Clone, PartialEq)]
@@ -22,6 +41,10 @@ pub enum StateProgress {
 	Add,
 	Remove,
 	Change,
+	/// Waiting for the user to confirm a project change and the channels that come with it.
+	Confirm,
+	/// Voting for a requested channel, or requesting a new one.
+	Request,
 }
 
 #[derive(Copy, Clone)]
@@ -34,10 +57,20 @@ pub enum State {
 #[derive(Copy, Clone, PartialEq)]
 pub enum Modifications {
 	Membership,
-	Roles,
-	Channels,
-	Projects,
-	Games,
+	/// An index into the guild's `assignables`.
+	Group(usize),
+	/// One of the guild's settings, in an /mcadmin session.
+	Setting(Setting),
+}
+
+impl Modifications {
+	/// Return the assignable group for this modification, if it is one.
+	fn assignable(&self, guild: &'static GuildConfig) -> Option<&'static Assignable> {
+		match self {
+			Modifications::Membership | Modifications::Setting(_) => None,
+			Modifications::Group(idx) => guild.assignables.get(*idx),
+		}
+	}
 }
 
 impl State {
@@ -60,6 +93,56 @@ impl State {
 	}
 }
 
+/// Keeps track of running MC sessions, so a shutdown can close their menus instead of leaving
+/// them dead.
+pub struct Sessions {
+	/// Flipped to true when we're shutting down. Every running session holds a receiver.
+	closing: watch::Sender<bool>,
+}
+
+impl Default for Sessions {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl Sessions {
+	pub fn new() -> Self {
+		let (closing, _) = watch::channel(false);
+		Self { closing }
+	}
+
+	/// Register a new session, or return `None` if we're shutting down.
+	fn join(&self) -> Option<watch::Receiver<bool>> {
+		let rx = self.closing.subscribe();
+		if *rx.borrow() {
+			return None;
+		}
+		Some(rx)
+	}
+
+	/// Stop accepting sessions, tell every running one to close, and wait for them to finish.
+	pub async fn close(&self) {
+		self.closing.send_replace(true);
+		info!("Closing {} MC sessions", self.closing.receiver_count());
+
+		if tokio::time::timeout(CLOSE_TIMEOUT, self.closing.closed()).await.is_err() {
+			warn!("Timed out waiting for {} MC sessions to close", self.closing.receiver_count());
+		}
+	}
+}
+
+/// A project join or leave waiting on the user to confirm the linked channels that come with it.
+pub struct Pending {
+	project: RoleId,
+	/// Joining the project, or leaving it?
+	join: bool,
+	/// The linked channels this would join or leave.
+	channels: Vec<ChannelId>,
+	/// Whether to leave the channels along with the project; set by the user's answer.
+	with_channels: bool,
+}
+
 // This whole struct is a mess. I'm still figuring out the best way to flow data in async Rust.
 pub struct MC {
 	/// The Serenity context.
@@ -68,12 +151,24 @@ pub struct MC {
 	/// Our bot message sent in response to the interaction.
 	mess: Option<Message>,
 
+	/// The token of the latest interaction, which we need to edit our (ephemeral) message.
+	token: String,
+
+	/// Changes when the bot is shutting down.
+	closing: watch::Receiver<bool>,
+
 	/// A ulid to represent this specific MC instance.
 	ulid: rusty_ulid::Ulid,
 
+	/// The guild the session was started in.
+	guild: &'static GuildConfig,
+
 	/// The user who started the interaction.
 	user: User,
 
+	/// Is this an /mcadmin session, changing the guild's settings instead of the user's roles?
+	admin: bool,
+
 	/// Which state we're currently in: MainMenu, Modification, or Done.
 	state: State,
 
@@ -85,7 +180,16 @@ pub struct MC {
 	/// If we processed an interaction and it provided a value to process, this contains it.
 	value: Option<String>,
 
-	/// Eventually: stores the current pagination value. Currently does nothing.
+	/// A project change waiting on the user's confirmation.
+	pending: Option<Pending>,
+
+	/// Shown above the menu after a step that needs explaining, like a change being rate limited.
+	notice: Option<String>,
+
+	/// Did we answer the last click with a form? If so, we also listen for it being submitted.
+	modal_open: bool,
+
+	/// Which page of `list` is shown, for lists too long for one select menu.
 	page: u8,
 
 	/// This contains the list of values to processor generated, used by the generator to build the message.
@@ -96,51 +200,98 @@ pub struct MC {
 }
 
 impl MC {
-	/// Start an MC instance from the /mc command.
-	pub async fn from_command(ctx: Context, command: ApplicationCommandInteraction) {
+	/// Start an MC instance from the /mc command, or an admin one from /mcadmin.
+	pub async fn from_command(ctx: Context, command: ApplicationCommandInteraction, guild: &'static GuildConfig, sessions: &Sessions, admin: bool) {
+		let closing = match sessions.join() {
+			Some(closing) => closing,
+			None => {
+				command.create_interaction_response(&ctx, |r| restarting_resp(r)).await.unwrap();
+				return;
+			}
+		};
+
 		let mut mc = Self {
 			ctx,
 			mess: None,
+			token: command.token.clone(),
+			closing,
 			ulid: rusty_ulid::Ulid::generate(),
+			guild,
 			user: command.user.clone(),
+			admin,
 			state: State::MainMenu,
 			modification: None,
 			value: None,
+			pending: None,
+			notice: None,
+			modal_open: false,
 			page: 0,
 			list: vec![],
 			running: true,
 		};
 
+		let timer = metrics::LATENCY.with_label_values(&["mc"]).start_timer();
 		command.create_interaction_response(&mc.ctx, |r| mc.initial_resp(r)).await.unwrap();
+		timer.observe_duration();
+		metrics::SESSIONS.with_label_values(&["started"]).inc();
 		mc.mess = command.get_interaction_response(&mc.ctx).await.ok();
 
-		mc.run().await;
+		let context = LogContext { session: Some(mc.ulid.to_string()), ..context() };
+		with_context(context, mc.run()).await;
 	}
 
 	/// Start an MC instance from the Launch! button being clicked.
-	pub async fn from_component(ctx: Context, component: MessageComponentInteraction) {
+	pub async fn from_component(ctx: Context, component: MessageComponentInteraction, guild: &'static GuildConfig, sessions: &Sessions) {
+		let closing = match sessions.join() {
+			Some(closing) => closing,
+			None => {
+				component.create_interaction_response(&ctx, |r| restarting_resp(r)).await.unwrap();
+				return;
+			}
+		};
+
 		let mut mc = Self {
 			ctx,
 			mess: None,
+			token: component.token.clone(),
+			closing,
 			ulid: rusty_ulid::Ulid::generate(),
+			guild,
 			user: component.user.clone(),
+			admin: false,
 			state: State::MainMenu,
 			modification: None,
 			value: None,
+			pending: None,
+			notice: None,
+			modal_open: false,
 			page: 0,
 			list: vec![],
 			running: true,
 		};
 
+		let timer = metrics::LATENCY.with_label_values(&["mc"]).start_timer();
 		component.create_interaction_response(&mc.ctx, |r| mc.initial_resp(r)).await.unwrap();
+		timer.observe_duration();
+		metrics::SESSIONS.with_label_values(&["started"]).inc();
 		mc.mess = component.get_interaction_response(&mc.ctx).await.ok();
 
-		mc.run().await;
+		let context = LogContext { session: Some(mc.ulid.to_string()), ..context() };
+		with_context(context, mc.run()).await;
 	}
 
 	async fn run(&mut self) {
 		debug!("MC#{}: Created by {}", self.ulid, self.user.tag());
 
+		// Keep a history of sessions for /stats.
+		{
+			let db = db(&self.ctx).await;
+			let db = db.lock().unwrap();
+			if db.execute("INSERT INTO mc_sessions (id, user_id, started_at, guild_id) VALUES (?1, ?2, ?3, ?4)", params![self.ulid.to_string(), self.user.id.0, Utc::now().timestamp(), self.guild.id.0]).is_err() {
+				error!("MC#{}: Error recording session", self.ulid);
+			}
+		}
+
 		while self.running {
 			match &self.mess {
 				None => {
@@ -150,23 +301,45 @@ impl MC {
 
 				// This is the core loop:
 				Some(mess) => {
-					// Await an interaction to our response message.
-					let mci = match mess
-						.await_component_interaction(&self.ctx)
-						.timeout(Duration::from_secs(3600))
-						.await {
+					// Await an interaction to our response message, unless we're shutting down.
+					let mci = tokio::select! {
+						ci = mess.await_component_interaction(&self.ctx).timeout(Duration::from_secs(3600)) => ci,
+						mi = mess.await_modal_interaction(&self.ctx).timeout(Duration::from_secs(3600)), if self.modal_open => {
+							if let Some(mi) = mi {
+								self.submit_modal(mi).await;
+							}
+							continue;
+						}
+						_ = self.closing.changed() => {
+							self.close().await;
+							metrics::SESSIONS.with_label_values(&["closed"]).inc();
+							return;
+						}
+					};
+					let mci = match mci {
 						Some(ci) => ci,
 						None => {
 							debug!("MC#{}: Interaction timeout!", self.ulid);
+							metrics::SESSIONS.with_label_values(&["expired"]).inc();
 							return;
 						}
 					};
+					self.token = mci.token.clone();
 
 					trace!("MC#{}: Received component ID \"{}\", processing...", self.ulid, mci.data.custom_id);
+					let timer = metrics::LATENCY.with_label_values(&["mc"]).start_timer();
 
 					// Send the interaction of to the handler for the current state.
+					self.modal_open = false;
 					(self.state.handler())(self, mci.clone());
 
+					// Some buttons open a form instead of changing the menu; we carry on once it's submitted.
+					if self.modal_open {
+						mci.create_interaction_response(&self.ctx, |f| self.generate_request_modal(f)).await.unwrap();
+						timer.observe_duration();
+						continue;
+					}
+
 					// Call the processor.
 					self.process().await;
 
@@ -177,11 +350,54 @@ impl MC {
 							(self.state.generator())(self, g)
 						})
 					}).await.unwrap();
+					timer.observe_duration();
 				}
 			}
 		}
 
 		debug!("MC#{}: Exited gracefully!", self.ulid);
+		metrics::SESSIONS.with_label_values(&["completed"]).inc();
+	}
+
+	/// Process a submitted form like a menu selection, then update the menu it was opened from.
+	async fn submit_modal(&mut self, mi: Arc<ModalSubmitInteraction>) {
+		self.token = mi.token.clone();
+		self.modal_open = false;
+
+		trace!("MC#{}: Received modal ID \"{}\", processing...", self.ulid, mi.data.custom_id);
+		let timer = metrics::LATENCY.with_label_values(&["mc"]).start_timer();
+
+		self.value = mi.data.components.iter()
+			.flat_map(|x| &x.components)
+			.find_map(|x| match x {
+				ActionRowComponent::InputText(input) => Some(input.value.clone()),
+				_ => None,
+			});
+
+		self.process().await;
+
+		mi.create_interaction_response(&self.ctx, |f| {
+			f.kind(InteractionResponseType::UpdateMessage);
+			f.interaction_response_data(|g| {
+				(self.state.generator())(self, g)
+			})
+		}).await.unwrap();
+		timer.observe_duration();
+	}
+
+	/// Replace our menu with a restarting notice. Interaction tokens only last 15 minutes, so this
+	/// can fail for idle sessions; their buttons just stop working, as before.
+	async fn close(&self) {
+		let mut edit = EditInteractionResponse::default();
+		edit.content(RESTARTING).components(|c| c).set_embeds(vec![]);
+
+		let map = Value::from(hashmap_to_json_map(edit.0));
+		if self.ctx.http.edit_original_interaction_response(&self.token, &map).await.is_err() {
+			debug!("MC#{}: Couldn't close menu; its token probably expired", self.ulid);
+			return;
+		}
+
+		debug!("MC#{}: Closed for shutdown", self.ulid);
 	}
 
 	fn initial_resp<'a, 'b>(&self, r: &'a mut CreateInteractionResponse<'b>) -> &'a mut CreateInteractionResponse<'b> {
@@ -190,4 +406,36 @@ impl MC {
 			(self.state.generator())(self, g)
 		})
 	}
+}
+
+fn restarting_resp<'a, 'b>(r: &'a mut CreateInteractionResponse<'b>) -> &'a mut CreateInteractionResponse<'b> {
+	r.kind(InteractionResponseType::ChannelMessageWithSource);
+	r.interaction_response_data(|d| {
+		d.flags(MessageFlags::EPHEMERAL);
+		d.content(RESTARTING)
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use std::time::Instant;
+
+	use super::*;
+
+	#[tokio::test]
+	async fn close_waits_for_sessions_then_refuses_new_ones() {
+		let sessions = Sessions::new();
+		let mut rx = sessions.join().unwrap();
+		let session = tokio::spawn(async move {
+			rx.changed().await.unwrap();
+			assert!(*rx.borrow());
+		});
+
+		let start = Instant::now();
+		sessions.close().await;
+		assert!(start.elapsed() < CLOSE_TIMEOUT);
+		session.await.unwrap();
+
+		assert!(sessions.join().is_none());
+	}
 }
\ No newline at end of file
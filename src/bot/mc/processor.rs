@@ -1,13 +1,40 @@
-use serenity::model::id::RoleId;
+use std::cmp::Reverse;
+
+use serenity::model::channel::ChannelType;
+use serenity::model::id::{ForumTagId, RoleId};
 use serenity::model::prelude::ChannelId;
+use serenity::model::Permissions;
 
-use crate::bot::config::{ALLOWED_MEMBERSHIPS, ALLOWED_PROJECTS, ALLOWED_ROLES, CAT_CHANNELS, CAT_GAMES, GUILD_ID};
-use crate::bot::mc::{MC, Modifications, State, StateProgress};
+use crate::bot::channel_requests::{self, Requested};
+use crate::bot::config::{Assignable, AssignableKind, CHANNEL_REQUEST_THRESHOLD, RoleList};
+use crate::bot::db::{audit, db};
+use crate::bot::mc::{MC, Modifications, Pending, State, StateProgress};
 use crate::bot::mc::generators::MenuOption;
-use crate::bot::mc::utils::{filter_chans, user_add_role, user_change_role, user_join_chan, user_leave_chan, user_remove_role};
+use crate::bot::mc::utils::{SLOW_DOWN, chan_icon, chan_in_group, chan_joinable, filter_chans, list_threads, project_bundle, rate_limit, user_add_role, user_change_role, user_in_chan, user_join_chan, user_join_project, user_join_thread, user_leave_chan, user_leave_project, user_leave_thread, user_remove_role, user_subscribe_tag, user_tag_subs, user_unsubscribe_tag};
+use crate::bot::metrics;
+use crate::bot::projects;
+use crate::bot::settings::{self, Setting};
+
+/// Roles holding any of these are never offered as self-service roles.
+const ELEVATED_PERMISSIONS: Permissions = Permissions::ADMINISTRATOR
+	.union(Permissions::MANAGE_GUILD)
+	.union(Permissions::MANAGE_ROLES)
+	.union(Permissions::MANAGE_CHANNELS)
+	.union(Permissions::MANAGE_MESSAGES)
+	.union(Permissions::MANAGE_NICKNAMES)
+	.union(Permissions::MANAGE_WEBHOOKS)
+	.union(Permissions::MANAGE_EMOJIS_AND_STICKERS)
+	.union(Permissions::MANAGE_EVENTS)
+	.union(Permissions::MANAGE_THREADS)
+	.union(Permissions::KICK_MEMBERS)
+	.union(Permissions::BAN_MEMBERS)
+	.union(Permissions::MODERATE_MEMBERS)
+	.union(Permissions::MENTION_EVERYONE);
 
 impl MC {
 	pub async fn process(&mut self) {
+		self.notice = None;
+
 		if let State::Modification(progress) = self.state {
 			// If we were given a value to process, do so now.
 			if self.value.is_some() {
@@ -19,208 +46,397 @@ impl MC {
 					self.state = State::MainMenu;
 				}
 			}
+		}
 
-			// We always call process_list, since process_value could have changed the existing list.
+		// We always call process_list, since process_value could have changed the existing list, or
+		// moved us to another step.
+		if let State::Modification(progress) = self.state {
 			self.process_list(progress).await;
 		}
 	}
 
 	async fn process_val(&mut self, progress: StateProgress) {
-		match self.modification.as_ref().unwrap() {
-            Modifications::Membership => {
-                match progress {
-                    StateProgress::Initial => unreachable!(),
-	                StateProgress::Add => unreachable!(),
-	                StateProgress::Remove => unreachable!(),
-	                // The ONLY valid state for a Membership modification is Change.
-                    StateProgress::Change => {
-	                    let role: RoleId = self.value.as_ref().unwrap().parse().unwrap();
-                        user_change_role(&self.ctx, &self.user, role, ALLOWED_MEMBERSHIPS).await;
-                    }
-                }
-            }
-			Modifications::Roles => {
+		let modif = self.modification.unwrap();
+
+		// Projects with linked channels ask first, showing the channels that come with them.
+		if let (Some(group), StateProgress::Add | StateProgress::Remove) = (modif.assignable(self.guild), progress) {
+			if group.kind == AssignableKind::Role(RoleList::Projects) {
+				let project: RoleId = self.value.as_ref().unwrap().parse().unwrap();
+				let join = progress == StateProgress::Add;
+
+				let channels = project_bundle(&self.ctx, self.guild.id, self.user.id, project, join).await;
+				if !channels.is_empty() {
+					self.pending = Some(Pending { project, join, channels, with_channels: join });
+					self.state = State::Modification(StateProgress::Confirm);
+					return;
+				}
+			}
+		}
+
+		match modif {
+			Modifications::Membership => {
 				match progress {
-					// If we're in process_val, we're already adding or removing, we can't be initial.
 					StateProgress::Initial => unreachable!(),
-					StateProgress::Change => unreachable!(), // Only relevant to Membership.
-					StateProgress::Add => {
+					StateProgress::Add => unreachable!(),
+					StateProgress::Remove => unreachable!(),
+					StateProgress::Confirm => unreachable!(),
+					StateProgress::Request => unreachable!(),
+					// The ONLY valid state for a Membership modification is Change.
+					StateProgress::Change => {
+						if !self.take_token().await {
+							return;
+						}
 						let role: RoleId = self.value.as_ref().unwrap().parse().unwrap();
-						user_add_role(&self.ctx, &self.user, role).await;
-					}
-					StateProgress::Remove => {
-						let role: RoleId = self.value.as_ref().unwrap().parse().unwrap();
-						user_remove_role(&self.ctx, &self.user, role).await;
+						user_change_role(&self.ctx, self.guild.id, &self.user, role, &self.guild.role_list(RoleList::Memberships)).await;
 					}
 				}
 			}
-			Modifications::Channels => {
-				match progress {
-					// If we're in process_val, we're already adding or removing, we can't be initial.
-					StateProgress::Initial => unreachable!(),
-					StateProgress::Change => unreachable!(), // Only relevant to Membership.
-					StateProgress::Add => {
-						let chan: ChannelId = self.value.as_ref().unwrap().parse().unwrap();
-						user_join_chan(&self.ctx, &self.user, chan).await;
-					}
-					StateProgress::Remove => {
-						let chan: ChannelId = self.value.as_ref().unwrap().parse().unwrap();
-						user_leave_chan(&self.ctx, &self.user, chan).await;
-					}
+			Modifications::Setting(setting) => {
+				let included = match progress {
+					StateProgress::Add => true,
+					StateProgress::Remove => false,
+					StateProgress::Initial | StateProgress::Change | StateProgress::Confirm | StateProgress::Request => unreachable!(),
+				};
+
+				let value: u64 = self.value.as_ref().unwrap().parse().unwrap();
+				let key = setting.key(self.guild);
+
+				let db = db(&self.ctx).await;
+				let db = db.lock().unwrap();
+				if settings::set(&db, self.guild.id, &key, value, included) {
+					let action = if included { "setting-add" } else { "setting-remove" };
+					audit(&db, &self.user.tag(), None, action, &format!("{}: {}", key, value));
 				}
 			}
-			Modifications::Projects => {
-				match progress {
+			Modifications::Group(_) => {
+				let group = modif.assignable(self.guild).unwrap();
+
+				// Everything below but a channel request changes the member, so it spends a token. A project
+				// that goes to Confirm returned above without changing anything, and spends it on confirming.
+				if progress != StateProgress::Request && !self.take_token().await {
+					return;
+				}
+
+				match (group.kind, progress) {
 					// If we're in process_val, we're already adding or removing, we can't be initial.
-					StateProgress::Initial => unreachable!(),
-					StateProgress::Change => unreachable!(), // Only relevant to Membership.
-					StateProgress::Add => {
+					(_, StateProgress::Initial) => unreachable!(),
+					(_, StateProgress::Change) => unreachable!(), // Only relevant to Membership.
+					(AssignableKind::Role(_), StateProgress::Confirm) => {
+						let pending = self.pending.take().unwrap();
+
+						if pending.join {
+							user_join_project(&self.ctx, self.guild.id, &self.user, pending.project, &pending.channels).await;
+							self.state = State::Modification(StateProgress::Add);
+						} else {
+							let channels = if pending.with_channels { pending.channels.as_slice() } else { &[] };
+							if let Err(why) = user_leave_project(&self.ctx, self.guild.id, &self.user, pending.project, channels).await {
+								self.notice = Some(why.message().to_string());
+							}
+							self.state = State::Modification(StateProgress::Remove);
+						}
+					}
+					(_, StateProgress::Confirm) => unreachable!(), // Only relevant to projects.
+					(AssignableKind::ChannelOverwrite(_), StateProgress::Request) => {
+						self.request_chan(group).await;
+					}
+					(_, StateProgress::Request) => unreachable!(), // Only relevant to channel groups.
+					(AssignableKind::Role(_), StateProgress::Add) => {
 						let role: RoleId = self.value.as_ref().unwrap().parse().unwrap();
-						user_add_role(&self.ctx, &self.user, role).await;
+						user_add_role(&self.ctx, self.guild.id, &self.user, role).await;
 					}
-					StateProgress::Remove => {
+					(AssignableKind::Role(_), StateProgress::Remove) => {
 						let role: RoleId = self.value.as_ref().unwrap().parse().unwrap();
-						user_remove_role(&self.ctx, &self.user, role).await;
+						user_remove_role(&self.ctx, self.guild.id, &self.user, role).await;
 					}
-				}
-			}
-			Modifications::Games => {
-				match progress {
-					// If we're in process_val, we're already adding or removing, we can't be initial.
-					StateProgress::Initial => unreachable!(),
-					StateProgress::Change => unreachable!(), // Only relevant to Membership.
-					StateProgress::Add => {
+					(AssignableKind::ChannelOverwrite(_), StateProgress::Add) => {
 						let chan: ChannelId = self.value.as_ref().unwrap().parse().unwrap();
 						user_join_chan(&self.ctx, &self.user, chan).await;
 					}
-					StateProgress::Remove => {
+					(AssignableKind::ChannelOverwrite(_), StateProgress::Remove) => {
 						let chan: ChannelId = self.value.as_ref().unwrap().parse().unwrap();
-						user_leave_chan(&self.ctx, &self.user, chan).await;
+						if let Err(why) = user_leave_chan(&self.ctx, &self.user, chan).await {
+							self.notice = Some(why.message().to_string());
+						}
+					}
+					(AssignableKind::Thread, StateProgress::Add) => {
+						let thread: ChannelId = self.value.as_ref().unwrap().parse().unwrap();
+						user_join_thread(&self.ctx, &self.user, thread).await;
+					}
+					(AssignableKind::Thread, StateProgress::Remove) => {
+						let thread: ChannelId = self.value.as_ref().unwrap().parse().unwrap();
+						user_leave_thread(&self.ctx, &self.user, thread).await;
+					}
+					(AssignableKind::ForumTag, StateProgress::Add) => {
+						let (forum, tag) = parse_tag_val(self.value.as_ref().unwrap());
+						user_subscribe_tag(&self.ctx, &self.user, forum, tag).await;
+					}
+					(AssignableKind::ForumTag, StateProgress::Remove) => {
+						let (forum, tag) = parse_tag_val(self.value.as_ref().unwrap());
+						user_unsubscribe_tag(&self.ctx, &self.user, forum, tag).await;
 					}
 				}
 			}
 		}
 	}
 
+	/// Take a rate limit token for a self-service change, leaving a notice if we're going too fast.
+	async fn take_token(&mut self) -> bool {
+		if rate_limit(&self.ctx, self.guild.id, &self.user).await.is_err() {
+			self.notice = Some(SLOW_DOWN.to_string());
+			return false;
+		}
+		true
+	}
+
 	async fn process_list(&mut self, progress: StateProgress) {
-		match self.modification.unwrap() {
-            Modifications::Membership => {
-                if progress == StateProgress::Initial {
-                    return;
-                }
-
-                let member = GUILD_ID.member(&self.ctx, self.user.id).await;
-                if member.is_err() {
-                    error!("Error retrieving member from UserId {}", self.user.id);
-                    return;
-                }
-                let member = member.unwrap();
-
-                let avail_roles: Vec<_> = ALLOWED_MEMBERSHIPS.iter().filter(|x| {
-                    if progress == StateProgress::Change {
-                        !member.roles.contains(x)
-                    } else {
-                        unreachable!()
-                    }
-                }).collect();
-
-                self.list = avail_roles.iter().map(|x| {
-                    let role = x.to_role_cached(&self.ctx);
-                    let role = role.unwrap();
-                    MenuOption {
-                        label: role.name,
-                        val: role.id.to_string(),
-                    }
-                }).collect();
-            }
-			Modifications::Roles => {
-				if progress == StateProgress::Initial {
-					return;
-				}
+		if progress == StateProgress::Initial || progress == StateProgress::Confirm {
+			return;
+		}
 
-				let member = GUILD_ID.member(&self.ctx, self.user.id).await;
-				if member.is_err() {
-					error!("Error retrieving member from UserId {}", self.user.id);
-					return;
-				}
-				let member = member.unwrap();
+		let modif = self.modification.unwrap();
 
-                let avail_roles: Vec<_> = ALLOWED_ROLES.iter().filter(|x| {
-                    if progress == StateProgress::Add {
-                        !member.roles.contains(x)
-                    } else if progress == StateProgress::Remove {
-                        member.roles.contains(x)
-                    } else {
-                        unreachable!()
-                    }
-                }).collect();
-
-                self.list = avail_roles.iter().map(|x| {
-                    let role = x.to_role_cached(&self.ctx).unwrap();
-                    MenuOption {
-                        label: role.name,
-                        val: role.id.to_string(),
-                    }
-                }).collect();
-			}
-			Modifications::Projects => {
-				if progress == StateProgress::Initial {
-					return;
-				}
+		if let Modifications::Setting(setting) = modif {
+			self.process_setting_list(setting, progress).await;
+			return;
+		}
 
-				let member = GUILD_ID.member(&self.ctx, self.user.id).await;
+		if progress == StateProgress::Request {
+			self.process_request_list(modif.assignable(self.guild).unwrap()).await;
+			return;
+		}
+
+		let group = modif.assignable(self.guild);
+		let kind = match group {
+			None => AssignableKind::Role(RoleList::Memberships),
+			Some(group) => group.kind,
+		};
 
+		match kind {
+			AssignableKind::Role(list) => {
+				let roles = self.guild.role_list(list);
+				let member = self.guild.id.member(&self.ctx, self.user.id).await;
 				if member.is_err() {
 					error!("Error retrieving member from UserId {}", self.user.id);
 					return;
 				}
-
 				let member = member.unwrap();
 
-				let avail_roles: Vec<_> = ALLOWED_PROJECTS.iter().filter(|x| {
-					if progress == StateProgress::Add {
-						!member.roles.contains(x)
-					} else if progress == StateProgress::Remove {
-						member.roles.contains(x)
-					} else {
-						unreachable!()
+				let avail_roles: Vec<_> = roles.iter().filter(|x| {
+					match progress {
+						StateProgress::Add | StateProgress::Change => !member.roles.contains(x),
+						StateProgress::Remove => member.roles.contains(x),
+						StateProgress::Initial | StateProgress::Confirm | StateProgress::Request => unreachable!(),
 					}
 				}).collect();
 
-				self.list = avail_roles.iter().map(|x| {
-					let role = x.to_role_cached(&self.ctx).unwrap();
-					MenuOption {
+				let db = db(&self.ctx).await;
+				let db = db.lock().unwrap();
+
+				// Lists changed through /mcadmin or /project-admin can still hold roles deleted since.
+				self.list = avail_roles.iter().filter_map(|x| {
+					let role = match x.to_role_cached(&self.ctx) {
+						Some(role) => role,
+						None => {
+							warn!("MC#{}: Skipping role {}, which no longer exists", self.ulid, x);
+							return None;
+						}
+					};
+
+					Some(MenuOption {
 						label: role.name,
 						val: role.id.to_string(),
-					}
+						emoji: None,
+						// Projects show what they're about, from their profile.
+						description: if list == RoleList::Projects { projects::summary(&db, role.id) } else { None },
+					})
 				}).collect();
 			}
-			Modifications::Channels => {
-				if progress == StateProgress::Initial {
-					return;
-				}
-
-				let chans = GUILD_ID.channels(&self.ctx).await.unwrap();
-				let chans = filter_chans(&self.ctx, &chans, CAT_CHANNELS, self.user.id, progress, false);
+			AssignableKind::ChannelOverwrite(_) => {
+				let chans = self.guild.id.channels(&self.ctx).await.unwrap();
+				let chans = filter_chans(&self.ctx, &chans, group.unwrap(), self.user.id, progress, false);
 
 				self.list = chans.iter().map(|x| MenuOption {
 					label: x.name.clone(),
 					val: x.id.to_string(),
+					emoji: Some(chan_icon(x.kind)),
+					description: None,
 				}).collect();
 			}
-			Modifications::Games => {
-				if progress == StateProgress::Initial {
-					return;
-				}
+			AssignableKind::Thread => {
+				let chans = self.guild.id.channels(&self.ctx).await.unwrap();
+				let threads = list_threads(&self.ctx, self.guild.id, &chans, self.user.id).await;
 
-				let chans = GUILD_ID.channels(&self.ctx).await.unwrap();
-				let chans = filter_chans(&self.ctx, &chans, CAT_GAMES, self.user.id, progress, false);
+				self.list = threads.iter()
+					.filter(|(_, following)| *following == (progress == StateProgress::Remove))
+					.map(|(x, _)| MenuOption {
+						label: x.name.clone(),
+						val: x.id.to_string(),
+						emoji: Some("🧵"),
+						description: None,
+					}).collect();
+			}
+			AssignableKind::ForumTag => {
+				let chans = self.guild.id.channels(&self.ctx).await.unwrap();
+				let subs = user_tag_subs(&self.ctx, self.user.id).await;
 
-				self.list = chans.iter().map(|x| MenuOption {
-					label: x.name.clone(),
+				let mut forums: Vec<_> = chans.values()
+					.filter(|x| x.kind == ChannelType::Forum && chan_joinable(x) && user_in_chan(&self.ctx, self.user.id, x))
+					.collect();
+				forums.sort_by_key(|x| x.position);
+
+				self.list = forums.iter()
+					.flat_map(|forum| forum.available_tags.iter().map(move |tag| (forum, tag)))
+					.filter(|(forum, tag)| subs.contains(&(forum.id, tag.id)) == (progress == StateProgress::Remove))
+					.map(|(forum, tag)| MenuOption {
+						label: format!("{}: {}", forum.name, tag.name),
+						val: format!("{}:{}", forum.id, tag.id),
+						emoji: Some(chan_icon(ChannelType::Forum)),
+						description: None,
+					}).collect();
+			}
+		}
+	}
+
+	/// Add the user's request for a channel, and create it once enough people have asked.
+	async fn request_chan(&mut self, group: &'static Assignable) {
+		let name = channel_requests::normalize(self.value.as_ref().unwrap());
+		if name.is_empty() {
+			self.notice = Some("Error: that name doesn't work as a channel name!".to_string());
+			return;
+		}
+
+		let chans = self.guild.id.channels(&self.ctx).await.unwrap();
+		if chans.values().any(|x| x.name == name && chan_in_group(x, group, true)) {
+			self.notice = Some(format!("There's already a #{} channel! Use {} to join it.", name, group.add_label));
+			return;
+		}
+
+		let requested = {
+			let db = db(&self.ctx).await;
+			let db = db.lock().unwrap();
+
+			let requested = channel_requests::request(&db, self.guild.id, group.id, &name, self.user.id, CHANNEL_REQUEST_THRESHOLD);
+			if requested.is_ok() {
+				audit(&db, &self.user.tag(), None, "channel-request", &format!("{}: {}", group.id, name));
+			}
+			requested
+		};
+
+		let requesters = match requested {
+			Ok(Requested::Waiting(count)) => {
+				self.notice = Some(format!("Requested #{}! {} of {} people have asked for it so far.", name, count, CHANNEL_REQUEST_THRESHOLD));
+				return;
+			}
+			Ok(Requested::Creating) => {
+				self.notice = Some(format!("Requested #{}! Enough people have asked, so it's being created now.", name));
+				return;
+			}
+			Ok(Requested::Claimed(requesters)) => requesters,
+			Err(_) => {
+				error!("Error saving {}'s request for channel {}", self.user.tag(), name);
+				self.notice = Some("Error: couldn't save your request, try again in a minute.".to_string());
+				return;
+			}
+		};
+
+		// Failed requests stay open, so the next one tries again.
+		self.notice = Some(match channel_requests::create(&self.ctx, self.guild, group, &name, &requesters).await {
+			Some(chan) => format!("That's {} requests, so <#{}> is open! Everyone who asked has been added.", requesters.len(), chan),
+			None => "Error: enough people asked, but the channel couldn't be created. Try again in a minute.".to_string(),
+		});
+	}
+
+	/// List the open requests in a group that the user hasn't asked for yet.
+	async fn process_request_list(&mut self, group: &'static Assignable) {
+		let db = db(&self.ctx).await;
+		let open = channel_requests::open(&db.lock().unwrap(), self.guild.id, group.id);
+
+		self.list = open.into_iter()
+			.filter(|(_, users)| !users.contains(&self.user.id))
+			.map(|(name, users)| MenuOption {
+				label: name.clone(),
+				val: name,
+				emoji: Some(chan_icon(ChannelType::Text)),
+				description: Some(format!("{} of {} requests", users.len(), CHANNEL_REQUEST_THRESHOLD)),
+			}).collect();
+	}
+
+	/// List what could be added to a setting, or what it holds to remove.
+	async fn process_setting_list(&mut self, setting: Setting, progress: StateProgress) {
+		let current = setting.values(self.guild);
+
+		// Everything this setting could hold, in display order.
+		let mut options: Vec<_> = match setting {
+			Setting::Roles(_) => {
+				let roles = self.guild.id.roles(&self.ctx).await.unwrap();
+
+				// We can only hand out roles below our own highest one.
+				let top = match self.guild.id.member(&self.ctx, self.ctx.cache.current_user_id()).await {
+					Ok(member) => member.roles.iter().filter_map(|x| roles.get(x)).map(|x| x.position).max().unwrap_or(0),
+					Err(_) => {
+						error!("Error retrieving our own member to list assignable roles");
+						metrics::discord_error("get_member");
+						0
+					}
+				};
+
+				let mut roles: Vec<_> = roles.into_values()
+					// Nobody can be given @everyone or a bot's own role, and self-service roles
+					// mustn't grant anything dangerous. Roles already in the setting stay removable.
+					.filter(|x| current.contains(&x.id.0) || (x.id.0 != self.guild.id.0 && !x.managed && x.position < top && !x.permissions.intersects(ELEVATED_PERMISSIONS)))
+					.collect();
+				roles.sort_by_key(|x| Reverse(x.position));
+
+				roles.into_iter().map(|x| MenuOption {
+					label: x.name,
 					val: x.id.to_string(),
-				}).collect();
+					emoji: None,
+					description: None,
+				}).collect()
+			}
+			Setting::Categories(_) | Setting::Excluded => {
+				let mut chans: Vec<_> = self.guild.id.channels(&self.ctx).await.unwrap()
+					.into_values()
+					.filter(|x| match setting {
+						Setting::Categories(_) => x.kind == ChannelType::Category,
+						// Only listed channels are worth excluding.
+						_ => chan_joinable(x) || current.contains(&x.id.0),
+					})
+					.collect();
+				chans.sort_by_key(|x| x.position);
+
+				chans.into_iter().map(|x| MenuOption {
+					label: x.name,
+					val: x.id.to_string(),
+					emoji: Some(chan_icon(x.kind)),
+					description: None,
+				}).collect()
+			}
+		};
+
+		// Roles and channels deleted since they were added can still be taken out.
+		if progress == StateProgress::Remove {
+			let missing: Vec<_> = current.iter().filter(|x| !options.iter().any(|o| o.val == x.to_string())).collect();
+			for id in missing {
+				warn!("MC#{}: Setting {} holds {}, which no longer exists", self.ulid, setting.key(self.guild), id);
+				options.push(MenuOption {
+					label: format!("Deleted ({})", id),
+					val: id.to_string(),
+					emoji: None,
+					description: None,
+				});
 			}
 		}
+
+		// Guilds have far more roles and channels than fit in one menu, so these are paged.
+		self.list = options.into_iter()
+			.filter(|x| current.contains(&x.val.parse().unwrap()) == (progress == StateProgress::Remove))
+			.collect();
 	}
+}
+
+/// Split a forum tag option's `forum:tag` value back into its IDs.
+fn parse_tag_val(val: &str) -> (ChannelId, ForumTagId) {
+	let (forum, tag) = val.split_once(':').unwrap();
+	(forum.parse().unwrap(), ForumTagId(tag.parse().unwrap()))
 }
\ No newline at end of file
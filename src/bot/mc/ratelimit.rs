@@ -0,0 +1,167 @@
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+use serenity::model::id::UserId;
+
+use crate::bot::config::{RATE_ALERT_STRIKES, RATE_ALERT_WINDOW, RATE_GLOBAL_BURST, RATE_GLOBAL_REFILL, RATE_USER_BURST, RATE_USER_REFILL};
+use crate::bot::metrics;
+
+/// Past this many tracked users, forget the ones who've been quiet long enough to be back to full.
+const PRUNE_AT: usize = 1000;
+
+/// A token bucket: holds up to `burst` tokens, and gains one back every `refill`.
+struct Bucket {
+	tokens: f64,
+	updated: Instant,
+}
+
+impl Bucket {
+	fn new(burst: u32) -> Self {
+		Self { tokens: burst as f64, updated: Instant::now() }
+	}
+
+	fn refill(&mut self, burst: u32, refill: Duration) {
+		let now = Instant::now();
+		self.tokens = (self.tokens + now.duration_since(self.updated).as_secs_f64() / refill.as_secs_f64()).min(burst as f64);
+		self.updated = now;
+	}
+
+	/// Whether there's a token to take.
+	fn ready(&mut self, burst: u32, refill: Duration) -> bool {
+		self.refill(burst, refill);
+		self.tokens >= 1.0
+	}
+
+	/// Take a token; only call this after `ready`.
+	fn take(&mut self) {
+		self.tokens -= 1.0;
+	}
+}
+
+/// How often a user has been refused lately.
+struct Strikes {
+	since: Instant,
+	count: u32,
+	alerted: bool,
+}
+
+struct Limits {
+	global: Bucket,
+	users: HashMap<UserId, Bucket>,
+	strikes: HashMap<UserId, Strikes>,
+}
+
+static LIMITS: LazyLock<Mutex<Limits>> = LazyLock::new(|| Mutex::new(Limits {
+	global: Bucket::new(RATE_GLOBAL_BURST),
+	users: HashMap::new(),
+	strikes: HashMap::new(),
+}));
+
+/// Whether a change may go ahead.
+pub enum Verdict {
+	Allowed,
+	Limited,
+	/// Refused, and the user has now been refused often enough that the mods should hear about it.
+	/// Holds how many times.
+	Alert(u32),
+}
+
+/// Take a token for one change by this user, from their own bucket and the global one.
+pub fn check(user: UserId) -> Verdict {
+	let mut limits = LIMITS.lock().unwrap();
+
+	if limits.users.len() > PRUNE_AT {
+		limits.users.retain(|_, x| {
+			x.refill(RATE_USER_BURST, RATE_USER_REFILL);
+			x.tokens < RATE_USER_BURST as f64
+		});
+		limits.strikes.retain(|_, x| x.since.elapsed() < RATE_ALERT_WINDOW);
+	}
+
+	// Check the user's own bucket first, so one person spamming can't drain everyone else's.
+	let bucket = limits.users.entry(user).or_insert_with(|| Bucket::new(RATE_USER_BURST));
+	if !bucket.ready(RATE_USER_BURST, RATE_USER_REFILL) {
+		metrics::RATE_LIMITED.with_label_values(&["user"]).inc();
+		return limits.strike(user);
+	}
+
+	// Nobody's misbehaving if we're just busy, so this doesn't count as a strike, and it doesn't
+	// cost them a token either.
+	if !limits.global.ready(RATE_GLOBAL_BURST, RATE_GLOBAL_REFILL) {
+		metrics::RATE_LIMITED.with_label_values(&["global"]).inc();
+		return Verdict::Limited;
+	}
+
+	limits.global.take();
+	limits.users.get_mut(&user).unwrap().take();
+	Verdict::Allowed
+}
+
+impl Limits {
+	fn strike(&mut self, user: UserId) -> Verdict {
+		let strikes = self.strikes.entry(user).or_insert_with(|| Strikes { since: Instant::now(), count: 0, alerted: false });
+		if strikes.since.elapsed() >= RATE_ALERT_WINDOW {
+			*strikes = Strikes { since: Instant::now(), count: 0, alerted: false };
+		}
+
+		strikes.count += 1;
+
+		// Only alert once per window, or the alerts become the spam.
+		if strikes.count >= RATE_ALERT_STRIKES && !strikes.alerted {
+			strikes.alerted = true;
+			return Verdict::Alert(strikes.count);
+		}
+
+		Verdict::Limited
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const REFILL: Duration = Duration::from_secs(10);
+
+	#[test]
+	fn bucket_refuses_when_empty() {
+		let mut bucket = Bucket::new(2);
+
+		for _ in 0..2 {
+			assert!(bucket.ready(2, REFILL));
+			bucket.take();
+		}
+		assert!(!bucket.ready(2, REFILL));
+	}
+
+	#[test]
+	fn bucket_refills_over_time() {
+		let mut bucket = Bucket::new(2);
+		bucket.tokens = 0.0;
+
+		// Pretend the last update was one and a half refills ago.
+		bucket.updated = Instant::now() - REFILL * 3 / 2;
+		assert!(bucket.ready(2, REFILL));
+		bucket.take();
+		assert!(!bucket.ready(2, REFILL));
+	}
+
+	#[test]
+	fn bucket_refills_up_to_burst() {
+		let mut bucket = Bucket::new(2);
+		bucket.updated = Instant::now() - REFILL * 10;
+
+		assert!(bucket.ready(2, REFILL));
+		assert_eq!(bucket.tokens, 2.0);
+	}
+
+	#[test]
+	fn checking_costs_nothing() {
+		let mut bucket = Bucket::new(1);
+
+		assert!(bucket.ready(1, REFILL));
+		assert!(bucket.ready(1, REFILL));
+		bucket.take();
+		assert!(!bucket.ready(1, REFILL));
+	}
+}
\ No newline at end of file
@@ -1,13 +1,22 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
 
+use chrono::Utc;
+use rusqlite::{Connection, params};
 use serenity::client::Context;
+use serenity::http::CacheHttp;
 use serenity::model::channel::{Channel, ChannelType, GuildChannel, PermissionOverwrite, PermissionOverwriteType};
-use serenity::model::id::{ChannelId, RoleId, UserId};
+use serenity::model::guild::Member;
+use serenity::model::id::{ChannelId, ForumTagId, GuildId, RoleId, UserId};
 use serenity::model::Permissions;
 use serenity::model::user::User;
 
-use crate::bot::config::{EXCLUDED_CHANNELS, GUILD_ID};
+use crate::bot::config::{Assignable, AssignableKind, ChannelAccess, guild_config};
+use crate::bot::db::db;
 use crate::bot::mc::StateProgress;
+use crate::bot::mc::ratelimit::{self, Verdict};
+use crate::bot::metrics;
+use crate::bot::projects;
 
 pub fn user_in_chan(ctx: &Context, user: UserId, channel: &GuildChannel) -> bool {
 	channel
@@ -16,96 +25,446 @@ pub fn user_in_chan(ctx: &Context, user: UserId, channel: &GuildChannel) -> bool
 		.unwrap_or(false)
 }
 
-pub fn filter_chans<'a>(ctx: &Context, chans: &'a HashMap<ChannelId, GuildChannel>, cat: ChannelId, user: UserId, progress: StateProgress, allow_excluded: bool) -> Vec<&'a GuildChannel> {
+/// The kinds of channel a user can join or leave.
+pub const JOINABLE_TYPES: &[ChannelType] = &[
+	ChannelType::Text,
+	ChannelType::Voice,
+	ChannelType::Stage,
+	ChannelType::Forum,
+];
+
+/// The icon shown next to a channel of this kind in menus.
+pub fn chan_icon(kind: ChannelType) -> &'static str {
+	match kind {
+		ChannelType::Voice => "🔊",
+		ChannelType::Stage => "🎙️",
+		ChannelType::Forum => "🗂️",
+		ChannelType::Category => "📁",
+		_ => "💬",
+	}
+}
+
+/// The permissions a member overwrite grants when joining a channel of this kind.
+pub fn chan_permissions(kind: ChannelType) -> Permissions {
+	match kind {
+		ChannelType::Voice | ChannelType::Stage => Permissions::VIEW_CHANNEL | Permissions::CONNECT,
+		_ => Permissions::VIEW_CHANNEL,
+	}
+}
+
+/// Is this member overwrite exactly one we'd create to let someone join a channel? Joins used to
+/// allow VIEW_CHANNEL and CONNECT on every kind of channel, so overwrites from back then count too.
+pub fn join_overwrite(kind: ChannelType, overwrite: &PermissionOverwrite) -> bool {
+	let legacy = Permissions::VIEW_CHANNEL | Permissions::CONNECT;
+	overwrite.deny.is_empty() && (overwrite.allow == chan_permissions(kind) || overwrite.allow == legacy)
+}
+
+/// The Mission Control tags an officer put in a channel's topic, like `[mc:hidden]`.
+#[derive(Default)]
+pub struct ChannelTags {
+	/// `[mc:hidden]`: never list this channel.
+	pub hidden: bool,
+	/// `[mc:joinable]`: list this channel in opt-in groups.
+	pub joinable: bool,
+	/// `[mc:joinable:<group id>]`: list this channel in these groups, wherever it lives.
+	pub groups: Vec<String>,
+}
+
+pub fn channel_tags(channel: &GuildChannel) -> ChannelTags {
+	channel.topic.as_deref().map(topic_tags).unwrap_or_default()
+}
+
+fn topic_tags(topic: &str) -> ChannelTags {
+	let mut tags = ChannelTags::default();
+
+	for tag in topic.split('[').filter_map(|x| x.split_once(']')).map(|(x, _)| x.trim()) {
+		match tag.strip_prefix("mc:") {
+			Some("hidden") => tags.hidden = true,
+			Some("joinable") => tags.joinable = true,
+			Some(other) => {
+				if let Some(group) = other.strip_prefix("joinable:") {
+					tags.groups.push(group.trim().to_string());
+				}
+			}
+			None => {}
+		}
+	}
+
+	tags
+}
+
+/// Is this channel listed under the given group, regardless of whether the user is in it?
+pub fn chan_in_group(channel: &GuildChannel, group: &Assignable, allow_excluded: bool) -> bool {
+	let source = match group.kind {
+		AssignableKind::ChannelOverwrite(source) => source,
+		_ => return false,
+	};
+
+	if !JOINABLE_TYPES.contains(&channel.kind) {
+		return false;
+	}
+
+	let tags = channel_tags(channel);
+
+	let config = guild_config(channel.guild_id);
+
+	let excluded = config.map(|x| x.excluded_channels().contains(&channel.id)).unwrap_or(false);
+	if !allow_excluded && (tags.hidden || excluded) {
+		return false;
+	}
+
+	if source.channels.contains(&channel.id) || tags.groups.iter().any(|x| x == group.id) {
+		return true;
+	}
+
+	match channel.parent_id {
+		Some(pid) => {
+			let categories = config.map(|x| x.categories(group)).unwrap_or_else(|| source.categories.to_vec());
+			categories.contains(&pid) && (!source.opt_in || tags.joinable)
+		}
+		None => false,
+	}
+}
+
+/// Which of its guild's groups, if any, lists this channel.
+pub fn chan_group(channel: &GuildChannel) -> Option<&'static Assignable> {
+	guild_config(channel.guild_id)?.assignables.iter().find(|x| chan_in_group(channel, x, true))
+}
+
+/// How joining this channel grants access, based on the group that lists it.
+pub fn chan_access(channel: &GuildChannel) -> ChannelAccess {
+	match chan_group(channel).map(|x| x.kind) {
+		Some(AssignableKind::ChannelOverwrite(source)) => source.access,
+		_ => ChannelAccess::Overwrite,
+	}
+}
+
+/// The name of a role for logging, falling back to its ID if there's no cache or it hasn't seen the
+/// role yet.
+pub fn role_name(cache_http: impl CacheHttp, role: RoleId) -> String {
+	cache_http.cache()
+		.and_then(|x| role.to_role_cached(x))
+		.map(|x| x.name)
+		.unwrap_or_else(|| role.to_string())
+}
+
+/// Is this channel listed under any of its guild's groups?
+pub fn chan_joinable(channel: &GuildChannel) -> bool {
+	guild_config(channel.guild_id)
+		.map(|g| g.assignables.iter().any(|x| chan_in_group(channel, x, false)))
+		.unwrap_or(false)
+}
+
+pub fn filter_chans<'a>(ctx: &Context, chans: &'a HashMap<ChannelId, GuildChannel>, group: &Assignable, user: UserId, progress: StateProgress, allow_excluded: bool) -> Vec<&'a GuildChannel> {
 	let mut ret: Vec<_> = chans
 		.values()
 		.filter(|x| {
-			let mut filt = false;
-
-			if let Some(pid) = x.parent_id {
-				if pid == cat && x.kind == ChannelType::Text && (allow_excluded || !EXCLUDED_CHANNELS.contains(&x.id)) {
-					if progress == StateProgress::Add {
-						filt = !user_in_chan(ctx, user, x);
-					} else if progress == StateProgress::Remove {
-						filt = user_in_chan(ctx, user, x);
-					}
-				}
+			if !chan_in_group(x, group, allow_excluded) {
+				return false;
 			}
 
-			filt
+			match progress {
+				StateProgress::Add => !user_in_chan(ctx, user, x),
+				StateProgress::Remove => user_in_chan(ctx, user, x),
+				_ => false,
+			}
 		})
 		.collect();
 
-	ret.sort_by(|a, b| a.position.cmp(&b.position));
+	ret.sort_by_key(|x| x.position);
 
 	ret
 }
 
-pub async fn user_change_role(ctx: &Context, user: &User, role: RoleId, roles: &[RoleId]) {
-	let member = GUILD_ID.member(ctx, user.id).await;
+/// Remember that we created a member overwrite for this user, so we only ever delete our own.
+pub async fn record_overwrite(ctx: &Context, cid: ChannelId, uid: UserId) {
+	let db = db(ctx).await;
+	let db = db.lock().unwrap();
+
+	if db.execute("INSERT OR REPLACE INTO overwrites (channel_id, user_id, created_at) VALUES (?1, ?2, ?3)", params![cid.0, uid.0, Utc::now().timestamp()]).is_err() {
+		error!("Error recording overwrite for UserId {} on ChannelId {}", uid, cid);
+	}
+}
+
+pub async fn forget_overwrite(ctx: &Context, cid: ChannelId, uid: UserId) {
+	let db = db(ctx).await;
+	let db = db.lock().unwrap();
+
+	if db.execute("DELETE FROM overwrites WHERE channel_id = ?1 AND user_id = ?2", [cid.0, uid.0]).is_err() {
+		error!("Error forgetting overwrite for UserId {} on ChannelId {}", uid, cid);
+	}
+}
+
+pub async fn overwrite_recorded(ctx: &Context, cid: ChannelId, uid: UserId) -> bool {
+	let db = db(ctx).await;
+	let db = db.lock().unwrap();
+
+	db.query_row("SELECT 1 FROM overwrites WHERE channel_id = ?1 AND user_id = ?2", [cid.0, uid.0], |_| Ok(())).is_ok()
+}
+
+/// Remember that we gave this user a role, for `/reconcile`.
+pub async fn record_role(ctx: &Context, uid: UserId, role: RoleId) -> bool {
+	let db = db(ctx).await;
+	let ok = db_record_role(&db.lock().unwrap(), uid, role);
+	ok
+}
+
+pub async fn forget_role(ctx: &Context, uid: UserId, role: RoleId) -> bool {
+	let db = db(ctx).await;
+	let ok = db_forget_role(&db.lock().unwrap(), uid, role);
+	ok
+}
+
+fn db_record_role(db: &Connection, uid: UserId, role: RoleId) -> bool {
+	let ok = db.execute("INSERT OR REPLACE INTO role_grants (user_id, role_id, created_at) VALUES (?1, ?2, ?3)", params![uid.0, role.0, Utc::now().timestamp()]).is_ok();
+	if !ok {
+		error!("Error recording role {} for UserId {}", role, uid);
+	}
+	ok
+}
+
+fn db_forget_role(db: &Connection, uid: UserId, role: RoleId) -> bool {
+	let ok = db.execute("DELETE FROM role_grants WHERE user_id = ?1 AND role_id = ?2", [uid.0, role.0]).is_ok();
+	if !ok {
+		error!("Error forgetting role {} for UserId {}", role, uid);
+	}
+	ok
+}
+
+/// What we tell someone whose change was refused for coming too fast.
+pub const SLOW_DOWN: &str = "Whoa, slow down! You're making changes too quickly. Give it a few seconds and try again.";
+
+/// A self-service change was refused for coming too fast.
+pub struct SlowDown;
+
+/// Take a token for a self-service change by this user, before making it. Someone who keeps
+/// hitting the limit is reported to the guild's mod channel.
+pub async fn rate_limit(ctx: &Context, guild: GuildId, user: &User) -> Result<(), SlowDown> {
+	let count = match ratelimit::check(user.id) {
+		Verdict::Allowed => return Ok(()),
+		Verdict::Limited => {
+			debug!("Rate limited a change by {}", user.tag());
+			return Err(SlowDown);
+		}
+		Verdict::Alert(count) => count,
+	};
+
+	warn!("{} has hit the change rate limit {} times", user.tag(), count);
+
+	if let Some(chan) = guild_config(guild).and_then(|x| x.mod_channel) {
+		let alert = format!("⚠️ <@{}> has hit Mission Control's rate limit {} times in the last few minutes.", user.id, count);
+		if chan.say(&ctx.http, alert).await.is_err() {
+			error!("Error alerting mods about {} in channel {}", user.tag(), chan);
+			metrics::discord_error("send_message");
+		}
+	}
+
+	Err(SlowDown)
+}
+
+pub async fn user_change_role(ctx: &Context, guild: GuildId, user: &User, role: RoleId, roles: &[RoleId]) {
+	let member = guild.member(ctx, user.id).await;
 	if member.is_err() {
 		error!("Error retrieving member from UserId {}", user.id);
+		metrics::discord_error("get_member");
 		return;
 	}
 	let mut member = member.unwrap();
 
-	match member.remove_roles(ctx, roles).await {
-		Ok(_) => {}
+	member_change_role(ctx, &*db(ctx).await, &mut member, role, roles).await;
+}
+
+/// The guts of `user_change_role`, split out so the CLI can run it without a gateway connection.
+/// Returns whether the member ended up with the new role.
+pub async fn member_change_role(cache_http: impl CacheHttp, db: &Mutex<Connection>, member: &mut Member, role: RoleId, roles: &[RoleId]) -> bool {
+	let user = member.user.clone();
+
+	match member.remove_roles(cache_http.http(), roles).await {
+		Ok(_) => {
+			let db = db.lock().unwrap();
+			for r in roles {
+				db_forget_role(&db, user.id, *r);
+			}
+		}
 		Err(_) => {
 			error!("Error stripping user {} of all membership roles!", user.tag());
+			metrics::discord_error("remove_roles");
 		}
 	};
 
-	match member.add_role(ctx, role).await {
+	match member.add_role(cache_http.http(), role).await {
 		Ok(_) => {
-			info!("Giving user {} role {}", user.tag(), role.to_role_cached(ctx).unwrap().name);
+			info!("Giving user {} role {}", user.tag(), role_name(&cache_http, role));
+			db_record_role(&db.lock().unwrap(), user.id, role);
+			metrics::change("membership", "add", true);
+			true
 		}
 		Err(_) => {
-			error!("Error giving user {} role {}", user.tag(), role.to_role_cached(ctx).unwrap().name);
+			error!("Error giving user {} role {}", user.tag(), role_name(&cache_http, role));
+			metrics::change("membership", "add", false);
+			metrics::discord_error("add_role");
+			false
 		}
 	}
 }
 
-pub async fn user_add_role(ctx: &Context, user: &User, role: RoleId) {
-	let member = GUILD_ID.member(ctx, user.id).await;
+/// Give a user a role and record it. Returns whether both worked.
+pub async fn user_add_role(ctx: &Context, guild: GuildId, user: &User, role: RoleId) -> bool {
+	let member = guild.member(ctx, user.id).await;
 	if member.is_err() {
 		error!("Error retrieving member from UserId {}", user.id);
-		return;
+		metrics::discord_error("get_member");
+		return false;
 	}
 	let mut member = member.unwrap();
 
 	match member.add_role(ctx, role).await {
 		Ok(_) => {
-			info!("Giving user {} role {}", user.tag(), role.to_role_cached(ctx).unwrap().name);
+			info!("Giving user {} role {}", user.tag(), role_name(ctx, role));
+			metrics::change("role", "add", true);
+			record_role(ctx, user.id, role).await
 		}
 		Err(_) => {
-			error!("Error giving user {} role {}", user.tag(), role.to_role_cached(ctx).unwrap().name);
+			error!("Error giving user {} role {}", user.tag(), role_name(ctx, role));
+			metrics::change("role", "add", false);
+			metrics::discord_error("add_role");
+			false
 		}
 	}
 }
 
-pub async fn user_remove_role(ctx: &Context, user: &User, role: RoleId) {
-	let member = GUILD_ID.member(ctx, user.id).await;
+/// Take a role from a user and forget it. Returns whether both worked.
+pub async fn user_remove_role(ctx: &Context, guild: GuildId, user: &User, role: RoleId) -> bool {
+	let member = guild.member(ctx, user.id).await;
 	if member.is_err() {
 		error!("Error retrieving member from UserId {}", user.id);
-		return;
+		metrics::discord_error("get_member");
+		return false;
 	}
 	let mut member = member.unwrap();
 
 	match member.remove_role(ctx, role).await {
 		Ok(_) => {
-			info!("Stripping user {} of role {}", user.tag(), role.to_role_cached(ctx).unwrap().name);
+			info!("Stripping user {} of role {}", user.tag(), role_name(ctx, role));
+			metrics::change("role", "remove", true);
+			forget_role(ctx, user.id, role).await
 		}
 		Err(_) => {
-			error!("Error stripping user {} of role {}", user.tag(), role.to_role_cached(ctx).unwrap().name);
+			error!("Error stripping user {} of role {}", user.tag(), role_name(ctx, role));
+			metrics::change("role", "remove", false);
+			metrics::discord_error("remove_role");
+			false
 		}
 	}
 }
 
+/// A project's linked channels that joining it would add the user to, or that leaving it could
+/// take them out of.
+pub async fn project_bundle(ctx: &Context, guild: GuildId, user: UserId, project: RoleId, join: bool) -> Vec<ChannelId> {
+	let linked = projects::channels(&db(ctx).await.lock().unwrap(), project);
+	if linked.is_empty() {
+		return vec![];
+	}
+
+	let chans = match guild.channels(ctx).await {
+		Ok(chans) => chans,
+		Err(_) => {
+			error!("Error retrieving channels for project {}", project);
+			metrics::discord_error("get_channels");
+			return vec![];
+		}
+	};
+
+	linked.iter()
+		.filter_map(|x| chans.get(x))
+		.filter(|x| chan_joinable(x) && user_in_chan(ctx, user, x) != join)
+		.map(|x| x.id)
+		.collect()
+}
+
+/// Give the user a project's role, and add them to the given linked channels.
+pub async fn user_join_project(ctx: &Context, guild: GuildId, user: &User, project: RoleId, channels: &[ChannelId]) {
+	user_add_role(ctx, guild, user, project).await;
+
+	for chan in channels {
+		user_join_chan(ctx, user, *chan).await;
+	}
+}
+
+/// Take a project's role from the user, and take them out of the given linked channels.
+pub async fn user_leave_project(ctx: &Context, guild: GuildId, user: &User, project: RoleId, channels: &[ChannelId]) -> Result<(), LeaveError> {
+	user_remove_role(ctx, guild, user, project).await;
+
+	// Keep going on failure, so one channel doesn't keep them in the rest.
+	let mut result = Ok(());
+	for chan in channels {
+		if let Err(why) = user_leave_chan(ctx, user, *chan).await {
+			result = Err(why);
+		}
+	}
+
+	result
+}
+
+/// Look up the access role backing a channel, if it has one that still exists.
+pub async fn existing_access_role(ctx: &Context, gchan: &GuildChannel) -> Option<RoleId> {
+	let existing: Option<RoleId> = {
+		let db = db(ctx).await;
+		let db = db.lock().unwrap();
+
+		db.query_row("SELECT role_id FROM access_roles WHERE channel_id = ?1", [gchan.id.0], |r| Ok(RoleId(r.get(0)?))).ok()
+	};
+
+	// Make sure nobody deleted the role out from under us.
+	existing.filter(|x| x.to_role_cached(ctx).is_some())
+}
+
+/// Look up the access role backing a channel, creating the role and its overwrite if needed.
+pub async fn chan_access_role(ctx: &Context, gchan: &GuildChannel) -> Option<RoleId> {
+	if let Some(role) = existing_access_role(ctx, gchan).await {
+		return Some(role);
+	}
+
+	let role = match gchan.guild_id.create_role(ctx, |r| {
+		r.name(format!("chan:{}", gchan.name)).permissions(Permissions::empty()).hoist(false).mentionable(false)
+	}).await {
+		Ok(role) => role,
+		Err(_) => {
+			error!("Error creating access role for channel {}", gchan.name);
+			metrics::discord_error("create_role");
+			return None;
+		}
+	};
+
+	let overwrite = PermissionOverwrite {
+		allow: chan_permissions(gchan.kind),
+		deny: Permissions::empty(),
+		kind: PermissionOverwriteType::Role(role.id),
+	};
+
+	if gchan.create_permission(ctx, &overwrite).await.is_err() {
+		error!("Error granting access role {} on channel {}", role.name, gchan.name);
+		metrics::discord_error("create_permission");
+		return None;
+	}
+
+	let db = db(ctx).await;
+	let db = db.lock().unwrap();
+
+	if db.execute("INSERT OR REPLACE INTO access_roles (channel_id, role_id) VALUES (?1, ?2)", [gchan.id.0, role.id.0]).is_err() {
+		error!("Error recording access role {} for channel {}", role.name, gchan.name);
+	}
+
+	info!("Created access role {} for channel {}", role.name, gchan.name);
+
+	Some(role.id)
+}
+
 pub async fn user_join_chan(ctx: &Context, user: &User, cid: ChannelId) {
 	let chan = cid.to_channel(ctx).await;
 	if chan.is_err() {
 		error!("Error retrieving channel from ChannelId {}", cid);
+		metrics::discord_error("get_channel");
 		return;
 	}
 	let chan = chan.unwrap();
@@ -114,18 +473,30 @@ pub async fn user_join_chan(ctx: &Context, user: &User, cid: ChannelId) {
 		Channel::Private(_) => { error!("Somehow got a private DM ChannelId?! {}", cid); }
 		Channel::Category(_) => { error!("Somehow got a category ChannelId?! {}", cid); }
 		Channel::Guild(gchan) => {
+			if chan_access(&gchan) == ChannelAccess::Role {
+				match chan_access_role(ctx, &gchan).await {
+					Some(role) => { user_add_role(ctx, gchan.guild_id, user, role).await; }
+					None => error!("Error adding user {} to channel {}", user.tag(), gchan.name()),
+				}
+				return;
+			}
+
 			let overwrite = PermissionOverwrite {
-				allow: Permissions::VIEW_CHANNEL | Permissions::CONNECT,
+				allow: chan_permissions(gchan.kind),
 				deny: Permissions::empty(),
 				kind: PermissionOverwriteType::Member(user.id),
 			};
 
 			match gchan.create_permission(ctx, &overwrite).await {
 				Ok(_) => {
-					info!("Added user {} to channel {}", user.tag(), gchan.name())
+					info!("Added user {} to channel {}", user.tag(), gchan.name());
+					record_overwrite(ctx, gchan.id, user.id).await;
+					metrics::change("channel", "add", true);
 				}
 				Err(_) => {
-					error!("Error adding user {} to channel {}", user.tag(), gchan.name())
+					error!("Error adding user {} to channel {}", user.tag(), gchan.name());
+					metrics::change("channel", "add", false);
+					metrics::discord_error("create_permission");
 				}
 			};
 		}
@@ -133,11 +504,30 @@ pub async fn user_join_chan(ctx: &Context, user: &User, cid: ChannelId) {
 	}
 }
 
-pub async fn user_leave_chan(ctx: &Context, user: &User, cid: ChannelId) {
+/// Why we couldn't take someone out of a channel.
+pub enum LeaveError {
+	/// Their access doesn't come from us, like a moderator's overwrite.
+	NotOurs,
+	/// Discord refused, or we couldn't find the channel.
+	Failed,
+}
+
+impl LeaveError {
+	/// What we tell the user.
+	pub fn message(&self) -> &'static str {
+		match self {
+			LeaveError::NotOurs => "Mission Control didn't add you to that channel, so it can't take you out. Ask a moderator!",
+			LeaveError::Failed => "Error: couldn't remove you from that channel, try again in a minute.",
+		}
+	}
+}
+
+pub async fn user_leave_chan(ctx: &Context, user: &User, cid: ChannelId) -> Result<(), LeaveError> {
 	let chan = cid.to_channel(ctx).await;
 	if chan.is_err() {
 		error!("Error retrieving channel from ChannelId {}", cid);
-		return;
+		metrics::discord_error("get_channel");
+		return Err(LeaveError::Failed);
 	}
 	let chan = chan.unwrap();
 
@@ -145,15 +535,319 @@ pub async fn user_leave_chan(ctx: &Context, user: &User, cid: ChannelId) {
 		Channel::Private(_) => { error!("Somehow got a private DM ChannelId?! {}", cid); }
 		Channel::Category(_) => { error!("Somehow got a category ChannelId?! {}", cid); }
 		Channel::Guild(gchan) => {
-			match gchan.delete_permission(ctx, PermissionOverwriteType::Member(user.id)).await {
+			let overwrite = gchan.permission_overwrites.iter().find(|x| x.kind == PermissionOverwriteType::Member(user.id));
+
+			// Leaving never creates anything, so a channel without its access role yet can only
+			// have let them in through a member overwrite.
+			if chan_access(&gchan) == ChannelAccess::Role {
+				if let Some(role) = existing_access_role(ctx, &gchan).await {
+					if !user_remove_role(ctx, gchan.guild_id, user, role).await {
+						return Err(LeaveError::Failed);
+					}
+
+					// Channels that haven't been migrated yet may still hold a member overwrite.
+					if overwrite.is_none() {
+						return Ok(());
+					}
+				}
+			}
+
+			// Overwrites we didn't create, like a moderator's mute, aren't ours to delete. Joins from
+			// before we recorded them look just like ours, so those are fine too.
+			let ours = overwrite_recorded(ctx, gchan.id, user.id).await || overwrite.map(|x| join_overwrite(gchan.kind, x)).unwrap_or(false);
+			if !ours {
+				warn!("Not removing user {} from channel {}: their overwrite wasn't created by us", user.tag(), gchan.name());
+				return Err(LeaveError::NotOurs);
+			}
+
+			return match gchan.delete_permission(ctx, PermissionOverwriteType::Member(user.id)).await {
 				Ok(_) => {
-					info!("Removing user {} from channel {}", user.tag(), gchan.name())
+					info!("Removing user {} from channel {}", user.tag(), gchan.name());
+					forget_overwrite(ctx, gchan.id, user.id).await;
+					metrics::change("channel", "remove", true);
+					Ok(())
 				}
 				Err(_) => {
-					error!("Error removing user {} from channel {}", user.tag(), gchan.name())
+					error!("Error removing user {} from channel {}", user.tag(), gchan.name());
+					metrics::change("channel", "remove", false);
+					metrics::discord_error("delete_permission");
+					Err(LeaveError::Failed)
 				}
 			};
 		}
 		_ => {}
 	}
+
+	Err(LeaveError::Failed)
+}
+
+/// Convert the plain member overwrites on a role-access channel into grants of its access role.
+/// Overwrites other than exactly what joining would set are left alone. Returns how many overwrites
+/// were converted, and how many were left alone.
+pub async fn migrate_chan_access(ctx: &Context, gchan: &GuildChannel) -> (usize, usize) {
+	let role = match chan_access_role(ctx, gchan).await {
+		Some(role) => role,
+		None => return (0, 0),
+	};
+
+	let mut count = 0;
+	let mut skipped = 0;
+
+	for overwrite in &gchan.permission_overwrites {
+		let uid = match overwrite.kind {
+			PermissionOverwriteType::Member(uid) => uid,
+			_ => continue,
+		};
+
+		if !join_overwrite(gchan.kind, overwrite) {
+			info!("Leaving overwrite for UserId {} in channel {} alone: it isn't a plain join", uid, gchan.name());
+			skipped += 1;
+			continue;
+		}
+
+		let member = gchan.guild_id.member(ctx, uid).await;
+		if member.is_err() {
+			error!("Error retrieving member from UserId {}", uid);
+			metrics::discord_error("get_member");
+			continue;
+		}
+		let mut member = member.unwrap();
+
+		if member.add_role(ctx, role).await.is_err() {
+			error!("Error giving user {} role {}", member.user.tag(), role_name(ctx, role));
+			metrics::discord_error("add_role");
+			continue;
+		}
+		record_role(ctx, uid, role).await;
+
+		match gchan.delete_permission(ctx, overwrite.kind).await {
+			Ok(_) => {
+				info!("Migrated user {} in channel {} to role {}", member.user.tag(), gchan.name(), role_name(ctx, role));
+				forget_overwrite(ctx, gchan.id, uid).await;
+				count += 1;
+			}
+			Err(_) => {
+				error!("Error removing overwrite for user {} from channel {}", member.user.tag(), gchan.name());
+				metrics::discord_error("delete_permission");
+			}
+		}
+	}
+
+	(count, skipped)
+}
+
+/// Every active public thread under a joinable channel the user can see, and whether they follow it.
+pub async fn list_threads(ctx: &Context, guild: GuildId, chans: &HashMap<ChannelId, GuildChannel>, user: UserId) -> Vec<(GuildChannel, bool)> {
+	let threads = match guild.get_active_threads(ctx).await {
+		Ok(data) => data.threads,
+		Err(_) => {
+			error!("Error retrieving active threads");
+			metrics::discord_error("get_active_threads");
+			return vec![];
+		}
+	};
+
+	let mut ret = vec![];
+
+	for thread in threads {
+		if thread.kind != ChannelType::PublicThread {
+			continue;
+		}
+
+		let visible = thread.parent_id
+			.and_then(|pid| chans.get(&pid))
+			.map(|parent| chan_joinable(parent) && user_in_chan(ctx, user, parent))
+			.unwrap_or(false);
+
+		if !visible {
+			continue;
+		}
+
+		let following = match thread.id.get_thread_members(ctx).await {
+			Ok(members) => members.iter().any(|x| x.user_id == Some(user)),
+			Err(_) => {
+				error!("Error retrieving members of thread {}", thread.name);
+				metrics::discord_error("get_thread_members");
+				continue;
+			}
+		};
+
+		ret.push((thread, following));
+	}
+
+	ret
+}
+
+pub async fn user_join_thread(ctx: &Context, user: &User, tid: ChannelId) {
+	match tid.add_thread_member(ctx, user.id).await {
+		Ok(_) => {
+			info!("Added user {} to thread {}", user.tag(), tid);
+			metrics::change("thread", "add", true);
+		}
+		Err(_) => {
+			error!("Error adding user {} to thread {}", user.tag(), tid);
+			metrics::change("thread", "add", false);
+			metrics::discord_error("add_thread_member");
+		}
+	}
+}
+
+pub async fn user_leave_thread(ctx: &Context, user: &User, tid: ChannelId) {
+	match tid.remove_thread_member(ctx, user.id).await {
+		Ok(_) => {
+			info!("Removing user {} from thread {}", user.tag(), tid);
+			metrics::change("thread", "remove", true);
+		}
+		Err(_) => {
+			error!("Error removing user {} from thread {}", user.tag(), tid);
+			metrics::change("thread", "remove", false);
+			metrics::discord_error("remove_thread_member");
+		}
+	}
+}
+
+/// Every (forum, tag) pair the user is subscribed to.
+pub async fn user_tag_subs(ctx: &Context, user: UserId) -> Vec<(ChannelId, ForumTagId)> {
+	let db = db(ctx).await;
+	let db = db.lock().unwrap();
+
+	let mut stmt = db.prepare("SELECT forum_id, tag_id FROM tag_subscriptions WHERE user_id = ?1").unwrap();
+	let rows = stmt.query_map([user.0], |r| Ok((ChannelId(r.get(0)?), ForumTagId(r.get(1)?))));
+
+	match rows {
+		Ok(rows) => rows.filter_map(|x| x.ok()).collect(),
+		Err(_) => {
+			error!("Error retrieving tag subscriptions for UserId {}", user);
+			vec![]
+		}
+	}
+}
+
+pub async fn user_subscribe_tag(ctx: &Context, user: &User, forum: ChannelId, tag: ForumTagId) {
+	let db = db(ctx).await;
+	let db = db.lock().unwrap();
+
+	match db.execute("INSERT OR IGNORE INTO tag_subscriptions (user_id, forum_id, tag_id) VALUES (?1, ?2, ?3)", [user.id.0, forum.0, tag.0]) {
+		Ok(_) => {
+			info!("Subscribed user {} to tag {} in forum {}", user.tag(), tag, forum);
+			metrics::change("tag", "add", true);
+		}
+		Err(_) => {
+			error!("Error subscribing user {} to tag {} in forum {}", user.tag(), tag, forum);
+			metrics::change("tag", "add", false);
+		}
+	}
+}
+
+pub async fn user_unsubscribe_tag(ctx: &Context, user: &User, forum: ChannelId, tag: ForumTagId) {
+	let db = db(ctx).await;
+	let db = db.lock().unwrap();
+
+	match db.execute("DELETE FROM tag_subscriptions WHERE user_id = ?1 AND forum_id = ?2 AND tag_id = ?3", [user.id.0, forum.0, tag.0]) {
+		Ok(_) => {
+			info!("Unsubscribed user {} from tag {} in forum {}", user.tag(), tag, forum);
+			metrics::change("tag", "remove", true);
+		}
+		Err(_) => {
+			error!("Error unsubscribing user {} from tag {} in forum {}", user.tag(), tag, forum);
+			metrics::change("tag", "remove", false);
+		}
+	}
+}
+
+/// Add everyone subscribed to one of a new forum post's tags to the post.
+pub async fn notify_tag_subs(ctx: &Context, thread: &GuildChannel) {
+	let forum = match thread.parent_id {
+		Some(pid) => pid,
+		None => return,
+	};
+
+	let users: Vec<UserId> = {
+		let db = db(ctx).await;
+		let db = db.lock().unwrap();
+
+		let mut stmt = db.prepare("SELECT DISTINCT user_id FROM tag_subscriptions WHERE forum_id = ?1 AND tag_id = ?2").unwrap();
+		let mut users = vec![];
+		for tag in &thread.applied_tags {
+			if let Ok(rows) = stmt.query_map([forum.0, tag.0], |r| Ok(UserId(r.get(0)?))) {
+				users.extend(rows.filter_map(|x| x.ok()));
+			}
+		}
+		users
+	};
+
+	let parent = ctx.cache.guild_channel(forum);
+
+	for user in users.into_iter().collect::<HashSet<_>>() {
+		// Don't pull people who've since left the forum into its posts.
+		if !parent.as_ref().map(|x| user_in_chan(ctx, user, x)).unwrap_or(false) {
+			continue;
+		}
+
+		match thread.id.add_thread_member(ctx, user).await {
+			Ok(_) => {
+				debug!("Added subscriber {} to forum post {}", user, thread.name)
+			}
+			Err(_) => {
+				error!("Error adding subscriber {} to forum post {}", user, thread.name);
+				metrics::discord_error("add_thread_member");
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn overwrite(allow: Permissions, deny: Permissions) -> PermissionOverwrite {
+		PermissionOverwrite { allow, deny, kind: PermissionOverwriteType::Member(UserId(1)) }
+	}
+
+	#[test]
+	fn voice_channels_need_connect() {
+		for kind in [ChannelType::Voice, ChannelType::Stage] {
+			assert!(chan_permissions(kind).contains(Permissions::VIEW_CHANNEL | Permissions::CONNECT));
+		}
+		for kind in [ChannelType::Text, ChannelType::Forum] {
+			assert_eq!(chan_permissions(kind), Permissions::VIEW_CHANNEL);
+		}
+	}
+
+	#[test]
+	fn join_overwrites_are_adoptable() {
+		assert!(join_overwrite(ChannelType::Text, &overwrite(Permissions::VIEW_CHANNEL, Permissions::empty())));
+		assert!(join_overwrite(ChannelType::Voice, &overwrite(Permissions::VIEW_CHANNEL | Permissions::CONNECT, Permissions::empty())));
+	}
+
+	#[test]
+	fn legacy_text_overwrites_are_adoptable() {
+		assert!(join_overwrite(ChannelType::Text, &overwrite(Permissions::VIEW_CHANNEL | Permissions::CONNECT, Permissions::empty())));
+	}
+
+	#[test]
+	fn moderator_overwrites_are_not_adoptable() {
+		// A mute denies something, and a moderator's grant allows more than we ever would.
+		assert!(!join_overwrite(ChannelType::Text, &overwrite(Permissions::VIEW_CHANNEL, Permissions::SEND_MESSAGES)));
+		assert!(!join_overwrite(ChannelType::Text, &overwrite(Permissions::VIEW_CHANNEL | Permissions::MANAGE_MESSAGES, Permissions::empty())));
+		assert!(!join_overwrite(ChannelType::Voice, &overwrite(Permissions::empty(), Permissions::SPEAK)));
+	}
+
+	#[test]
+	fn partial_overwrites_are_not_adoptable() {
+		assert!(!join_overwrite(ChannelType::Text, &overwrite(Permissions::empty(), Permissions::empty())));
+		assert!(!join_overwrite(ChannelType::Voice, &overwrite(Permissions::VIEW_CHANNEL, Permissions::empty())));
+		assert!(!join_overwrite(ChannelType::Voice, &overwrite(Permissions::CONNECT, Permissions::empty())));
+	}
+
+	#[test]
+	fn tags_in_topics() {
+		let tags = topic_tags("Rocket talk [mc:joinable] [ mc:joinable:games ] [mc:hidden][not:ours]");
+		assert!(tags.joinable);
+		assert!(tags.hidden);
+		assert_eq!(tags.groups, vec!["games"]);
+
+		let tags = topic_tags("Just a topic [mc:joinable");
+		assert!(!tags.joinable && !tags.hidden && tags.groups.is_empty());
+	}
 }
\ No newline at end of file
@@ -0,0 +1,104 @@
+use std::sync::LazyLock;
+
+use prometheus::{Encoder, HistogramOpts, HistogramVec, IntCounterVec, Opts, Registry, TextEncoder};
+
+static REGISTRY: LazyLock<Registry> = LazyLock::new(Registry::new);
+
+fn counter(name: &str, help: &str, labels: &[&str]) -> IntCounterVec {
+	let counter = IntCounterVec::new(Opts::new(name, help), labels).unwrap();
+	REGISTRY.register(Box::new(counter.clone())).unwrap();
+	counter
+}
+
+/// Slash commands received, by command name.
+pub static COMMANDS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+	counter("mc_commands_total", "Slash commands received", &["command"])
+});
+
+/// MC sessions by event: started, completed, expired (timed out) or closed (by a shutdown).
+pub static SESSIONS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+	counter("mc_sessions_total", "Mission Control sessions started and ended", &["event"])
+});
+
+/// Changes made to members, by kind (membership, role, channel, thread or tag), action (add or
+/// remove) and outcome (ok or error).
+pub static CHANGES: LazyLock<IntCounterVec> = LazyLock::new(|| {
+	counter("mc_changes_total", "Role, channel and subscription changes", &["kind", "action", "outcome"])
+});
+
+/// Failed Discord API calls, by what we were trying to do.
+pub static DISCORD_ERRORS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+	counter("mc_discord_errors_total", "Failed Discord API calls", &["operation"])
+});
+
+/// Commands and buttons refused because the user lacked access, by command name.
+pub static DENIED: LazyLock<IntCounterVec> = LazyLock::new(|| {
+	counter("mc_denied_total", "Commands refused for lack of permission", &["command"])
+});
+
+/// Changes refused by the rate limiter, by which limit: user or global.
+pub static RATE_LIMITED: LazyLock<IntCounterVec> = LazyLock::new(|| {
+	counter("mc_rate_limited_total", "Changes refused for coming too fast", &["limit"])
+});
+
+/// How long it takes to respond to an interaction, by kind: command, component, or mc (one step
+/// of an MC session).
+pub static LATENCY: LazyLock<HistogramVec> = LazyLock::new(|| {
+	let histogram = HistogramVec::new(
+		HistogramOpts::new("mc_interaction_duration_seconds", "Time taken to handle an interaction"),
+		&["kind"],
+	).unwrap();
+	REGISTRY.register(Box::new(histogram.clone())).unwrap();
+	histogram
+});
+
+/// Count a change to a member.
+pub fn change(kind: &str, action: &str, ok: bool) {
+	let outcome = if ok { "ok" } else { "error" };
+	CHANGES.with_label_values(&[kind, action, outcome]).inc();
+}
+
+/// Count a failed Discord API call.
+pub fn discord_error(operation: &str) {
+	DISCORD_ERRORS.with_label_values(&[operation]).inc();
+}
+
+/// Every metric in the Prometheus text format.
+pub fn render() -> String {
+	// A metric only shows up once it has a series, so create the ones whose labels we know up front.
+	// The rest appear the first time they're counted.
+	LazyLock::force(&COMMANDS);
+	LazyLock::force(&CHANGES);
+	LazyLock::force(&DISCORD_ERRORS);
+	LazyLock::force(&DENIED);
+	for event in ["started", "completed", "expired", "closed"] {
+		SESSIONS.with_label_values(&[event]);
+	}
+	for limit in ["user", "global"] {
+		RATE_LIMITED.with_label_values(&[limit]);
+	}
+	for kind in ["command", "component", "mc"] {
+		LATENCY.with_label_values(&[kind]);
+	}
+
+	let mut buf = vec![];
+	TextEncoder::new().encode(&REGISTRY.gather(), &mut buf).unwrap();
+	String::from_utf8(buf).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn render_lists_known_series() {
+		change("role", "add", true);
+		change("role", "add", true);
+
+		let text = render();
+		assert!(text.contains("mc_sessions_total{event=\"expired\"} 0"));
+		assert!(text.contains("mc_rate_limited_total{limit=\"global\"} 0"));
+		assert!(text.contains("mc_interaction_duration_seconds_count{kind=\"component\"} 0"));
+		assert!(text.contains("mc_changes_total{action=\"add\",kind=\"role\",outcome=\"ok\"} 2"));
+	}
+}
\ No newline at end of file
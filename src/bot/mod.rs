@@ -1,7 +1,45 @@
+use std::sync::atomic::AtomicI64;
+
+mod channel_requests;
 pub mod config;
+pub mod db;
 mod events;
+mod inactivity;
+pub mod http;
 mod commands;
 mod components;
 pub mod mc;
+mod metrics;
+mod permissions;
+mod projects;
+mod reconcile;
+mod rollover;
+pub mod scheduler;
+mod settings;
+mod stats;
+pub mod roster;
+
+pub struct Bot {
+	/// Runs periodic and one-shot background jobs; started on `ready`.
+	pub scheduler: scheduler::Scheduler,
+	/// Running MC sessions, closed on shutdown.
+	pub sessions: mc::Sessions,
+	/// When `ready` last fired, as a unix timestamp, or 0 if it hasn't yet.
+	pub last_ready: AtomicI64,
+}
+
+impl Bot {
+	pub fn new() -> Self {
+		Self {
+			scheduler: scheduler::Scheduler::new(),
+			sessions: mc::Sessions::new(),
+			last_ready: AtomicI64::new(0),
+		}
+	}
 
-pub struct Bot;
+	/// Close every MC session and stop background jobs, ahead of the gateway going away.
+	pub async fn shutdown(&self) {
+		self.sessions.close().await;
+		self.scheduler.shutdown().await;
+	}
+}
\ No newline at end of file
@@ -0,0 +1,104 @@
+use serenity::model::guild::Member;
+use serenity::model::Permissions;
+
+use crate::bot::config::{Access, COMMAND_ACCESS, GuildConfig, OFFICER_PERMISSIONS};
+use crate::bot::metrics;
+
+/// Who may use a command in this guild.
+pub fn access(guild: &GuildConfig, command: &str) -> Access {
+	guild.command_access.iter()
+		.chain(COMMAND_ACCESS)
+		.find(|(name, _)| *name == command)
+		.map(|(_, access)| *access)
+		.unwrap_or(Access::Officer)
+}
+
+/// May this member use a command? Interactions carry the member's permissions in the channel they
+/// were used in, so we don't need the cache for this.
+pub fn allowed(guild: &GuildConfig, command: &str, member: Option<&Member>) -> bool {
+	let access = access(guild, command);
+	if access == Access::Everyone {
+		return true;
+	}
+
+	let member = match member {
+		Some(member) => member,
+		None => return false,
+	};
+
+	match access {
+		Access::Everyone => true,
+		Access::Officer => is_officer(guild, Some(member)),
+		Access::Permissions(needed) => has_permissions(member, needed),
+	}
+}
+
+fn has_permissions(member: &Member, needed: Permissions) -> bool {
+	let perms = member.permissions.unwrap_or_else(Permissions::empty);
+	perms.administrator() || perms.contains(needed)
+}
+
+/// Does this member hold one of the guild's officer roles, or `OFFICER_PERMISSIONS`?
+pub fn is_officer(guild: &GuildConfig, member: Option<&Member>) -> bool {
+	match member {
+		Some(member) => has_permissions(member, OFFICER_PERMISSIONS) || member.roles.iter().any(|x| guild.officer_roles.contains(x)),
+		None => false,
+	}
+}
+
+/// Log and count a denied attempt to use a command.
+pub fn denied(guild: &GuildConfig, command: &str, member: Option<&Member>) {
+	let user = member.map(|x| x.user.tag()).unwrap_or_else(|| "Unknown".to_string());
+	warn!("Denied {} access to /{} in guild {}", user, command, guild.id);
+	metrics::DENIED.with_label_values(&[command]).inc();
+}
+
+/// The permissions to register a command with, so Discord hides it from people who can't use it.
+/// Discord can't limit a command to roles, so where officers are picked by role the command stays
+/// visible and we check on use.
+pub fn default_member_permissions(guild: &GuildConfig, command: &str) -> Option<Permissions> {
+	match access(guild, command) {
+		Access::Everyone => None,
+		Access::Officer if !guild.officer_roles.is_empty() => None,
+		Access::Officer => Some(OFFICER_PERMISSIONS),
+		Access::Permissions(needed) => Some(needed),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use serenity::model::id::RoleId;
+
+	use crate::bot::config::GUILDS;
+
+	const GUILD: GuildConfig = GuildConfig {
+		officer_roles: &[],
+		command_access: &[("stats", Access::Everyone), ("mc", Access::Permissions(Permissions::MANAGE_ROLES))],
+		..GUILDS[0]
+	};
+
+	#[test]
+	fn guild_access_overrides_defaults() {
+		assert!(access(&GUILD, "stats") == Access::Everyone);
+		assert!(access(&GUILD, "mc") == Access::Permissions(Permissions::MANAGE_ROLES));
+		assert!(access(&GUILD, "join") == Access::Everyone);
+		assert!(access(&GUILD, "no-such-command") == Access::Officer);
+	}
+
+	#[test]
+	fn officer_commands_hidden_only_without_officer_roles() {
+		assert_eq!(default_member_permissions(&GUILD, "join"), None);
+		assert_eq!(default_member_permissions(&GUILD, "reconcile"), Some(OFFICER_PERMISSIONS));
+		assert_eq!(default_member_permissions(&GUILD, "mc"), Some(Permissions::MANAGE_ROLES));
+
+		let by_role = GuildConfig { officer_roles: &[RoleId(1)], ..GUILD };
+		assert_eq!(default_member_permissions(&by_role, "reconcile"), None);
+	}
+
+	#[test]
+	fn everyone_commands_need_no_member() {
+		assert!(allowed(&GUILD, "join", None));
+		assert!(!allowed(&GUILD, "reconcile", None));
+	}
+}
\ No newline at end of file
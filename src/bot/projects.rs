@@ -0,0 +1,251 @@
+use chrono::Utc;
+use rusqlite::{Connection, OptionalExtension, params};
+use serenity::client::Context;
+use serenity::model::channel::{ChannelType, PermissionOverwrite, PermissionOverwriteType};
+use serenity::model::guild::Role;
+use serenity::model::id::{ChannelId, RoleId, UserId};
+use serenity::model::Permissions;
+use serenity::model::user::User;
+
+use crate::bot::config::{GuildConfig, RoleList};
+use crate::bot::db::{audit, db};
+use crate::bot::mc::utils::{chan_permissions, user_add_role, user_remove_role};
+use crate::bot::metrics;
+use crate::bot::roster::fetch_members;
+use crate::bot::settings::{self, Setting};
+
+/// What we know about a project beyond its role, set through `/project`.
+#[derive(Default)]
+pub struct Profile {
+	pub description: Option<String>,
+	/// When and where the project meets, free-form.
+	pub meeting: Option<String>,
+	pub leads: Vec<UserId>,
+	/// Channels that belong to the project.
+	pub channels: Vec<ChannelId>,
+	pub links: Vec<String>,
+}
+
+/// Load a project's profile; projects nobody has filled in get an empty one.
+pub fn profile(db: &Connection, project: RoleId) -> Profile {
+	let details: Option<(Option<String>, Option<String>, String)> = db.query_row(
+		"SELECT description, meeting, links FROM project_profiles WHERE role_id = ?1",
+		[project.0],
+		|r| Ok((r.get(0)?, r.get(1)?, r.get(2)?)),
+	).optional().unwrap_or_else(|_| {
+		error!("Error retrieving profile for project {}", project);
+		None
+	});
+	let (description, meeting, links) = details.unwrap_or_default();
+
+	Profile {
+		description,
+		meeting,
+		leads: ids(db, "SELECT user_id FROM project_leads WHERE role_id = ?1", project).into_iter().map(UserId).collect(),
+		channels: channels(db, project),
+		links: links.lines().map(|x| x.to_string()).collect(),
+	}
+}
+
+/// The channels linked to a project.
+pub fn channels(db: &Connection, project: RoleId) -> Vec<ChannelId> {
+	ids(db, "SELECT channel_id FROM project_channels WHERE role_id = ?1", project).into_iter().map(ChannelId).collect()
+}
+
+fn ids(db: &Connection, query: &str, project: RoleId) -> Vec<u64> {
+	let mut stmt = db.prepare(query).unwrap();
+	let rows = stmt.query_map([project.0], |r| r.get(0)).unwrap();
+	rows.filter_map(|x| x.ok()).collect()
+}
+
+/// Save a project's description, meeting time and links. Leads and channels are saved as they change.
+pub fn save_details(db: &Connection, project: RoleId, profile: &Profile) -> bool {
+	db.execute(
+		"INSERT OR REPLACE INTO project_profiles (role_id, description, meeting, links) VALUES (?1, ?2, ?3, ?4)",
+		params![project.0, profile.description, profile.meeting, profile.links.join("\n")],
+	).is_ok()
+}
+
+pub fn set_lead(db: &Connection, project: RoleId, user: UserId, lead: bool) -> bool {
+	let query = if lead {
+		"INSERT OR IGNORE INTO project_leads (role_id, user_id) VALUES (?1, ?2)"
+	} else {
+		"DELETE FROM project_leads WHERE role_id = ?1 AND user_id = ?2"
+	};
+
+	db.execute(query, [project.0, user.0]).is_ok()
+}
+
+pub fn set_channel(db: &Connection, project: RoleId, channel: ChannelId, linked: bool) -> bool {
+	let query = if linked {
+		"INSERT OR IGNORE INTO project_channels (role_id, channel_id) VALUES (?1, ?2)"
+	} else {
+		"DELETE FROM project_channels WHERE role_id = ?1 AND channel_id = ?2"
+	};
+
+	db.execute(query, [project.0, channel.0]).is_ok()
+}
+
+pub fn is_lead(db: &Connection, project: RoleId, user: UserId) -> bool {
+	db.query_row("SELECT 1 FROM project_leads WHERE role_id = ?1 AND user_id = ?2", [project.0, user.0], |_| Ok(())).is_ok()
+}
+
+/// A one-line summary of a project for menus: its description and when it meets.
+pub fn summary(db: &Connection, project: RoleId) -> Option<String> {
+	let profile = profile(db, project);
+
+	match (profile.description, profile.meeting) {
+		(Some(description), Some(meeting)) => Some(format!("{} · Meets {}", description, meeting)),
+		(Some(description), None) => Some(description),
+		(None, Some(meeting)) => Some(format!("Meets {}", meeting)),
+		(None, None) => None,
+	}
+}
+
+/// Create a project: a role, a private channel only that role can see, and an entry in the guild's
+/// project list. The error says how far we got, since Discord can fail us halfway.
+pub async fn create(ctx: &Context, guild: &GuildConfig, name: &str, category: Option<ChannelId>, lead: Option<&User>, actor: &str) -> Result<(RoleId, ChannelId), String> {
+	let role = guild.id.create_role(&ctx.http, |r| r.name(name).mentionable(true).permissions(Permissions::empty())).await;
+	let role = match role {
+		Ok(role) => role.id,
+		Err(_) => {
+			metrics::discord_error("create_role");
+			return Err("Error: couldn't create the role.".to_string());
+		}
+	};
+
+	let overwrites = vec![
+		// @everyone shares the guild's ID.
+		PermissionOverwrite { allow: Permissions::empty(), deny: Permissions::VIEW_CHANNEL, kind: PermissionOverwriteType::Role(RoleId(guild.id.0)) },
+		PermissionOverwrite { allow: chan_permissions(ChannelType::Text), deny: Permissions::empty(), kind: PermissionOverwriteType::Role(role) },
+		// Keep seeing it ourselves, so we can archive it later.
+		PermissionOverwrite { allow: Permissions::VIEW_CHANNEL, deny: Permissions::empty(), kind: PermissionOverwriteType::Member(ctx.cache.current_user_id()) },
+	];
+
+	let channel = guild.id.create_channel(&ctx.http, |c| {
+		c.name(name.to_lowercase().replace(' ', "-")).kind(ChannelType::Text).permissions(overwrites);
+		if let Some(category) = category {
+			c.category(category);
+		}
+		c
+	}).await;
+	let channel = match channel {
+		Ok(channel) => channel.id,
+		Err(_) => {
+			metrics::discord_error("create_channel");
+			return Err(format!("Error: created <@&{}>, but couldn't create its channel.", role));
+		}
+	};
+
+	{
+		let db = db(ctx).await;
+		let db = db.lock().unwrap();
+
+		let registered = settings::set(&db, guild.id, &Setting::Roles(RoleList::Projects).key(guild), role.0, true);
+		if !registered || !set_channel(&db, role, channel, true) {
+			return Err(format!("Error: created <@&{}> and <#{}>, but couldn't register the project.", role, channel));
+		}
+
+		if let Some(lead) = lead {
+			set_lead(&db, role, lead.id, true);
+		}
+
+		audit(&db, actor, None, "project-create", name);
+	}
+
+	if let Some(lead) = lead {
+		user_add_role(ctx, guild.id, lead, role).await;
+	}
+
+	Ok((role, channel))
+}
+
+/// Archive a project: save its roster, move its channels under `category`, take its role from
+/// everyone, and drop it from the guild's project list. Returns how many members it had.
+pub async fn archive(ctx: &Context, guild: &GuildConfig, project: &Role, category: ChannelId, actor: &str) -> Result<usize, String> {
+	let members = match fetch_members(&ctx.http, guild.id).await {
+		Ok(members) => members.into_iter().filter(|x| x.roles.contains(&project.id)).collect::<Vec<_>>(),
+		Err(_) => {
+			metrics::discord_error("get_members");
+			return Err("Error: couldn't retrieve the project's members.".to_string());
+		}
+	};
+
+	// Save the roster first; everything after this loses information.
+	let channels = {
+		let db = db(ctx).await;
+		let db = db.lock().unwrap();
+
+		let leads = profile(&db, project.id).leads;
+		let saved = db.execute(
+			"INSERT OR REPLACE INTO project_archives (role_id, name, archived_at) VALUES (?1, ?2, ?3)",
+			params![project.id.0, project.name, Utc::now().timestamp()],
+		).is_ok() && members.iter().all(|x| db.execute(
+			"INSERT OR REPLACE INTO project_archive_members (role_id, user_id, lead) VALUES (?1, ?2, ?3)",
+			params![project.id.0, x.user.id.0, leads.contains(&x.user.id)],
+		).is_ok());
+
+		if !saved {
+			return Err("Error: couldn't save the roster, so nothing was changed.".to_string());
+		}
+
+		channels(&db, project.id)
+	};
+
+	for channel in &channels {
+		if channel.edit(&ctx.http, |c| c.category(category)).await.is_err() {
+			error!("Error moving channel {} of project {} to the archive", channel, project.name);
+			metrics::discord_error("edit_channel");
+		}
+	}
+
+	for member in &members {
+		user_remove_role(ctx, guild.id, &member.user, project.id).await;
+	}
+
+	let db = db(ctx).await;
+	let db = db.lock().unwrap();
+	if !settings::set(&db, guild.id, &Setting::Roles(RoleList::Projects).key(guild), project.id.0, false) {
+		return Err(format!("Error: archived {}, but couldn't remove it from the project list.", project.name));
+	}
+	audit(&db, actor, None, "project-archive", &format!("{}: {} members", project.name, members.len()));
+
+	Ok(members.len())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::bot::db::open_memory;
+
+	#[test]
+	fn profiles_round_trip() {
+		let db = open_memory();
+		let project = RoleId(1);
+
+		assert!(profile(&db, project).description.is_none());
+		assert_eq!(summary(&db, project), None);
+
+		let details = Profile {
+			description: Some("Builds rockets".to_string()),
+			meeting: Some("Fridays at 5".to_string()),
+			links: vec!["https://example.com".to_string(), "https://example.org".to_string()],
+			..Default::default()
+		};
+		assert!(save_details(&db, project, &details));
+		assert!(set_lead(&db, project, UserId(2), true));
+		assert!(set_channel(&db, project, ChannelId(3), true));
+
+		let saved = profile(&db, project);
+		assert_eq!(saved.meeting.as_deref(), Some("Fridays at 5"));
+		assert_eq!(saved.links, details.links);
+		assert_eq!(saved.leads, vec![UserId(2)]);
+		assert_eq!(saved.channels, vec![ChannelId(3)]);
+		assert_eq!(summary(&db, project).as_deref(), Some("Builds rockets · Meets Fridays at 5"));
+
+		assert!(is_lead(&db, project, UserId(2)));
+		assert!(!is_lead(&db, RoleId(4), UserId(2)));
+		assert!(set_lead(&db, project, UserId(2), false));
+		assert!(!is_lead(&db, project, UserId(2)));
+	}
+}
\ No newline at end of file
@@ -0,0 +1,224 @@
+use std::collections::HashSet;
+use std::fmt;
+
+use serenity::client::Context;
+use serenity::model::channel::PermissionOverwriteType;
+use serenity::model::id::{ChannelId, RoleId, UserId};
+
+use crate::bot::config::{GuildConfig, RoleList};
+use crate::bot::db::db;
+use crate::bot::mc::utils::{chan_joinable, forget_overwrite, forget_role, join_overwrite, record_overwrite, record_role};
+use crate::bot::roster::fetch_members;
+
+/// A difference between what we recorded granting and what's actually on Discord.
+pub enum Drift {
+	/// A member overwrite on a joinable channel that we never created. `adoptable` overwrites look
+	/// exactly like one of ours; the rest (mutes and the like) were set by a moderator.
+	UnknownOverwrite { channel: ChannelId, user: UserId, adoptable: bool },
+	/// We recorded creating this overwrite, but it's no longer on the channel.
+	MissingOverwrite { channel: ChannelId, user: UserId },
+	/// A member holds a bot-managed role that we never gave them.
+	UnknownRole { user: UserId, role: RoleId },
+	/// We recorded giving this role, but the member no longer has it.
+	MissingRole { user: UserId, role: RoleId },
+}
+
+impl fmt::Display for Drift {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Drift::UnknownOverwrite { channel, user, adoptable: true } => write!(f, "Untracked overwrite for <@{}> on <#{}>", user, channel),
+			Drift::UnknownOverwrite { channel, user, adoptable: false } => write!(f, "Moderator overwrite for <@{}> on <#{}> (left alone)", user, channel),
+			Drift::MissingOverwrite { channel, user } => write!(f, "Recorded overwrite for <@{}> on <#{}> is gone", user, channel),
+			Drift::UnknownRole { user, role } => write!(f, "Untracked role <@&{}> on <@{}>", role, user),
+			Drift::MissingRole { user, role } => write!(f, "Recorded role <@&{}> on <@{}> is gone", role, user),
+		}
+	}
+}
+
+/// What's on Discord, boiled down to what drift is worked out from.
+struct OnDiscord {
+	joinable: HashSet<ChannelId>,
+	/// Member overwrites on joinable channels, as (channel, user, adoptable).
+	overwrites: Vec<(ChannelId, UserId, bool)>,
+	/// Every bot-managed role each member holds, as (user, role).
+	roles: HashSet<(UserId, RoleId)>,
+	/// Every role in the guild.
+	guild_roles: HashSet<RoleId>,
+}
+
+/// Compare what's on Discord against what we've recorded.
+fn compare(discord: &OnDiscord, overwrites: &HashSet<(ChannelId, UserId)>, grants: &HashSet<(UserId, RoleId)>) -> Vec<Drift> {
+	let mut drift = vec![];
+
+	for (channel, user, adoptable) in &discord.overwrites {
+		if !overwrites.contains(&(*channel, *user)) {
+			drift.push(Drift::UnknownOverwrite { channel: *channel, user: *user, adoptable: *adoptable });
+		}
+	}
+
+	let seen: HashSet<_> = discord.overwrites.iter().map(|(channel, user, _)| (*channel, *user)).collect();
+	for (channel, user) in overwrites {
+		// Channels that are no longer joinable aren't looked at, so don't call them missing.
+		if discord.joinable.contains(channel) && !seen.contains(&(*channel, *user)) {
+			drift.push(Drift::MissingOverwrite { channel: *channel, user: *user });
+		}
+	}
+
+	for (user, role) in &discord.roles {
+		if !grants.contains(&(*user, *role)) {
+			drift.push(Drift::UnknownRole { user: *user, role: *role });
+		}
+	}
+
+	// Grants are recorded for every guild together; only look at this one's roles.
+	for (user, role) in grants.iter().filter(|(_, role)| discord.guild_roles.contains(role)) {
+		if !discord.roles.contains(&(*user, *role)) {
+			drift.push(Drift::MissingRole { user: *user, role: *role });
+		}
+	}
+
+	drift
+}
+
+/// Compare every joinable channel's member overwrites and every member's bot-managed roles in a guild
+/// against what we've recorded.
+pub async fn find_drift(ctx: &Context, config: &GuildConfig) -> Result<Vec<Drift>, String> {
+	let (overwrites, grants, access_roles) = {
+		let db = db(ctx).await;
+		let db = db.lock().unwrap();
+
+		let overwrites: HashSet<(ChannelId, UserId)> = db
+			.prepare("SELECT channel_id, user_id FROM overwrites").unwrap()
+			.query_map([], |r| Ok((ChannelId(r.get(0)?), UserId(r.get(1)?)))).unwrap()
+			.filter_map(|x| x.ok())
+			.collect();
+
+		let grants: HashSet<(UserId, RoleId)> = db
+			.prepare("SELECT user_id, role_id FROM role_grants").unwrap()
+			.query_map([], |r| Ok((UserId(r.get(0)?), RoleId(r.get(1)?)))).unwrap()
+			.filter_map(|x| x.ok())
+			.collect();
+
+		let access_roles: Vec<RoleId> = db
+			.prepare("SELECT role_id FROM access_roles").unwrap()
+			.query_map([], |r| Ok(RoleId(r.get(0)?))).unwrap()
+			.filter_map(|x| x.ok())
+			.collect();
+
+		(overwrites, grants, access_roles)
+	};
+
+	let chans = config.id.channels(ctx).await.map_err(|x| format!("Error retrieving channels: {}", x))?;
+	let guild_roles = config.id.roles(ctx).await.map_err(|x| format!("Error retrieving roles: {}", x))?;
+
+	// The cache only holds every member on small guilds, so ask Discord for all of them.
+	let members = fetch_members(&ctx.http, config.id).await.map_err(|x| format!("Error retrieving members: {}", x))?;
+
+	let joinable: Vec<_> = chans.values().filter(|x| chan_joinable(x)).collect();
+
+	let overwrites_on = joinable.iter()
+		.flat_map(|chan| chan.permission_overwrites.iter().filter_map(|overwrite| match overwrite.kind {
+			PermissionOverwriteType::Member(user) => Some((chan.id, user, join_overwrite(chan.kind, overwrite))),
+			_ => None,
+		}))
+		.collect();
+
+	let managed: HashSet<RoleId> = config.role_list(RoleList::Memberships).iter()
+		.chain(&config.role_list(RoleList::Roles))
+		.chain(&config.role_list(RoleList::Projects))
+		.chain(&access_roles)
+		.copied()
+		.collect();
+
+	let discord = OnDiscord {
+		joinable: joinable.iter().map(|x| x.id).collect(),
+		overwrites: overwrites_on,
+		roles: members.iter()
+			.flat_map(|member| member.roles.iter().filter(|x| managed.contains(x)).map(|role| (member.user.id, *role)))
+			.collect(),
+		guild_roles: guild_roles.into_keys().collect(),
+	};
+
+	Ok(compare(&discord, &overwrites, &grants))
+}
+
+/// Bring our records in line with Discord, leaving Discord itself alone. Grants that look like ours
+/// are adopted and vanished ones are forgotten; moderator overwrites are never recorded. Returns how
+/// many records were updated.
+pub async fn update_records(ctx: &Context, drift: &[Drift]) -> usize {
+	let mut count = 0;
+
+	for d in drift {
+		match d {
+			Drift::UnknownOverwrite { adoptable: false, .. } => continue,
+			Drift::UnknownOverwrite { channel, user, adoptable: true } => record_overwrite(ctx, *channel, *user).await,
+			Drift::MissingOverwrite { channel, user } => forget_overwrite(ctx, *channel, *user).await,
+			Drift::UnknownRole { user, role } => { record_role(ctx, *user, *role).await; }
+			Drift::MissingRole { user, role } => { forget_role(ctx, *user, *role).await; }
+		}
+
+		count += 1;
+	}
+
+	count
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn report(drift: &[Drift]) -> Vec<String> {
+		let mut report: Vec<_> = drift.iter().map(|x| x.to_string()).collect();
+		report.sort();
+		report
+	}
+
+	#[test]
+	fn matching_records_have_no_drift() {
+		let discord = OnDiscord {
+			joinable: HashSet::from([ChannelId(10)]),
+			overwrites: vec![(ChannelId(10), UserId(1), true)],
+			roles: HashSet::from([(UserId(1), RoleId(20))]),
+			guild_roles: HashSet::from([RoleId(20)]),
+		};
+
+		let overwrites = HashSet::from([(ChannelId(10), UserId(1))]);
+		let grants = HashSet::from([(UserId(1), RoleId(20))]);
+		assert!(compare(&discord, &overwrites, &grants).is_empty());
+	}
+
+	#[test]
+	fn untracked_grants_are_reported() {
+		let discord = OnDiscord {
+			joinable: HashSet::from([ChannelId(10)]),
+			overwrites: vec![(ChannelId(10), UserId(1), true), (ChannelId(10), UserId(2), false)],
+			roles: HashSet::from([(UserId(1), RoleId(20))]),
+			guild_roles: HashSet::from([RoleId(20)]),
+		};
+
+		assert_eq!(report(&compare(&discord, &HashSet::new(), &HashSet::new())), vec![
+			"Moderator overwrite for <@2> on <#10> (left alone)",
+			"Untracked overwrite for <@1> on <#10>",
+			"Untracked role <@&20> on <@1>",
+		]);
+	}
+
+	#[test]
+	fn vanished_grants_are_reported() {
+		let discord = OnDiscord {
+			joinable: HashSet::from([ChannelId(10)]),
+			overwrites: vec![],
+			roles: HashSet::new(),
+			guild_roles: HashSet::from([RoleId(20)]),
+		};
+
+		// Records for channels that aren't joinable anymore and roles from other guilds don't count.
+		let overwrites = HashSet::from([(ChannelId(10), UserId(1)), (ChannelId(11), UserId(1))]);
+		let grants = HashSet::from([(UserId(1), RoleId(20)), (UserId(1), RoleId(30))]);
+
+		assert_eq!(report(&compare(&discord, &overwrites, &grants)), vec![
+			"Recorded overwrite for <@1> on <#10> is gone",
+			"Recorded role <@&20> on <@1> is gone",
+		]);
+	}
+}
\ No newline at end of file
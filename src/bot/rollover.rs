@@ -0,0 +1,368 @@
+use std::sync::Mutex;
+
+use chrono::{Datelike, Local, NaiveDate, Utc};
+use rusqlite::{Connection, params};
+use serenity::client::Context;
+use serenity::model::application::component::ButtonStyle;
+use serenity::model::application::interaction::InteractionResponseType;
+use serenity::model::application::interaction::message_component::MessageComponentInteraction;
+use serenity::model::id::{GuildId, UserId};
+
+use crate::bot::config::{GUILDS, GuildConfig, ROLLOVER_DATES, ROLLOVER_NOTICE_DAYS, RoleList};
+use crate::bot::db::{audit, db};
+use crate::bot::mc::utils::member_change_role;
+use crate::bot::roster::{fetch_members, term_for};
+
+/// Rollover dates further in the past than this are ignored, so a fresh deploy doesn't act on them.
+const ROLLOVER_GRACE_DAYS: i64 = 30;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Action {
+	/// No dues for the new term: Member becomes Friend.
+	Lapse,
+	/// Graduation term has passed: Member becomes Alumnus.
+	Graduate,
+}
+
+impl Action {
+	fn as_str(&self) -> &'static str {
+		match self {
+			Action::Lapse => "lapse",
+			Action::Graduate => "graduate",
+		}
+	}
+
+	/// What the change does, for officers.
+	fn describe(&self) -> &'static str {
+		match self {
+			Action::Lapse => "Member to Friend (no dues)",
+			Action::Graduate => "Member to Alumnus (graduated)",
+		}
+	}
+
+	fn from_str(s: &str) -> Option<Action> {
+		match s {
+			"lapse" => Some(Action::Lapse),
+			"graduate" => Some(Action::Graduate),
+			_ => None,
+		}
+	}
+}
+
+/// Turn a term like "Spring 2027" into something sortable.
+fn parse_term(term: &str) -> Option<(i32, u32)> {
+	let (season, year) = term.trim().split_once(' ')?;
+	let season = match season.to_lowercase().as_str() {
+		"spring" => 0,
+		"summer" => 1,
+		"fall" => 2,
+		_ => return None,
+	};
+	Some((year.trim().parse().ok()?, season))
+}
+
+/// What happens to a member at the start of `term`: graduation if their graduation term has passed, otherwise a lapse if they haven't paid dues for it.
+fn action_for(db: &Connection, uid: u64, term: &str) -> Option<Action> {
+	let graduation: Option<String> = db.query_row("SELECT term FROM graduations WHERE user_id = ?1", [uid], |r| r.get(0)).ok();
+	let graduated = graduation.and_then(|x| parse_term(&x)).map(|x| Some(x) < parse_term(term)).unwrap_or(false);
+	let paid = db.query_row("SELECT 1 FROM dues WHERE user_id = ?1 AND term = ?2", params![uid, term], |_| Ok(())).is_ok();
+
+	if graduated {
+		Some(Action::Graduate)
+	} else if !paid {
+		Some(Action::Lapse)
+	} else {
+		None
+	}
+}
+
+/// The guild whose memberships roll over, if any.
+fn rollover_guild() -> Option<&'static GuildConfig> {
+	GUILDS.iter().find(|x| x.rollover)
+}
+
+/// Check whether a rollover is due, and warn or apply it. Safe to call as often as you like; the
+/// scheduler runs it hourly. Given a guild, it only does anything if that's the rollover guild.
+pub async fn tick(ctx: &Context, only: Option<GuildId>) {
+	let guild = match rollover_guild() {
+		Some(guild) if only.map(|x| x == guild.id).unwrap_or(true) => guild,
+		_ => return,
+	};
+
+	let today = Local::now().date_naive();
+
+	for (month, day) in ROLLOVER_DATES {
+		let date = match NaiveDate::from_ymd_opt(today.year(), *month, *day) {
+			Some(date) => date,
+			None => {
+				error!("Invalid rollover date {}/{}", month, day);
+				continue;
+			}
+		};
+
+		let until = (date - today).num_days();
+		if !(-ROLLOVER_GRACE_DAYS..=ROLLOVER_NOTICE_DAYS).contains(&until) {
+			continue;
+		}
+
+		let term = term_for(date);
+
+		let notified = {
+			let db = db(ctx).await;
+			let db = db.lock().unwrap();
+			db.query_row("SELECT 1 FROM rollover_runs WHERE term = ?1", [&term], |_| Ok(())).is_ok()
+		};
+
+		if !notified {
+			notify(ctx, guild, &term).await;
+		}
+
+		if until <= 0 {
+			apply(ctx, guild, &term).await;
+		}
+	}
+}
+
+/// Work out who the rollover into `term` affects, record it, and DM each of them.
+async fn notify(ctx: &Context, guild: &GuildConfig, term: &str) {
+	let members = match fetch_members(&ctx.http, guild.id).await {
+		Ok(members) => members,
+		Err(_) => {
+			error!("Error retrieving members for the {} rollover", term);
+			return;
+		}
+	};
+
+	let mut pending = vec![];
+
+	{
+		let db = db(ctx).await;
+		let db = db.lock().unwrap();
+
+		for member in members.iter().filter(|x| x.roles.contains(&guild.member)) {
+			let uid = member.user.id.0;
+
+			let action = match action_for(&db, uid, term) {
+				Some(action) => action,
+				None => continue,
+			};
+
+			if db.execute("INSERT OR IGNORE INTO rollover_pending (user_id, term, action) VALUES (?1, ?2, ?3)", params![uid, term, action.as_str()]).is_err() {
+				error!("Error recording pending rollover for user {}", member.user.tag());
+				continue;
+			}
+
+			pending.push((member.user.clone(), action));
+		}
+
+		db.execute("INSERT OR REPLACE INTO rollover_runs (term, notified_at) VALUES (?1, ?2)", params![term, Utc::now().timestamp()]).unwrap();
+		audit(&db, "rollover", None, "rollover-notify", &format!("{}: {} members affected", term, pending.len()));
+	}
+
+	for (user, action) in pending {
+		let text = match action {
+			Action::Lapse => format!("We don't have SEDS dues on record for you for {}, so your membership will change to Friend of SEDS in {} days.", term, ROLLOVER_NOTICE_DAYS),
+			Action::Graduate => format!("Congratulations on graduating! Your membership will change to SEDS Alumnus for {} in {} days.", term, ROLLOVER_NOTICE_DAYS),
+		};
+
+		let sent = user.direct_message(ctx, |m| {
+			m
+				.content(format!("{}\nIf this is a mistake, let the officers know:", text))
+				.components(|c| {
+					c.create_action_row(|ar| {
+						ar.create_button(|b| {
+							b.custom_id(format!("contest-rollover:{}", term)).label("This is a mistake").style(ButtonStyle::Danger)
+						})
+					})
+				})
+		}).await;
+
+		if sent.is_err() {
+			warn!("Couldn't DM user {} about the {} rollover", user.tag(), term);
+		}
+	}
+}
+
+/// Apply every uncontested pending change for `term` whose notice period has passed.
+async fn apply(ctx: &Context, guild: &GuildConfig, term: &str) {
+	let cutoff = Utc::now().timestamp() - ROLLOVER_NOTICE_DAYS * 86400;
+
+	let (db, pending) = {
+		let db = db(ctx).await;
+		let pending: Vec<(UserId, String)> = {
+			let conn = db.lock().unwrap();
+			let mut stmt = conn.prepare("
+				SELECT p.user_id, p.action FROM rollover_pending p JOIN rollover_runs r ON p.term = r.term
+				WHERE p.term = ?1 AND p.contested = 0 AND p.applied = 0 AND r.notified_at <= ?2
+			").unwrap();
+			let rows = stmt.query_map(params![term, cutoff], |r| Ok((UserId(r.get(0)?), r.get(1)?))).unwrap();
+			rows.filter_map(|x| x.ok()).collect()
+		};
+		(db, pending)
+	};
+
+	for (uid, action) in pending {
+		if let Some(action) = Action::from_str(&action) {
+			apply_change(ctx, guild, &db, uid, term, action).await;
+		}
+	}
+}
+
+/// Make one member's pending change for `term`, returning whether it's done.
+async fn apply_change(ctx: &Context, guild: &GuildConfig, db: &Mutex<Connection>, uid: UserId, term: &str, action: Action) -> bool {
+	let role = match action {
+		Action::Lapse => guild.friend,
+		Action::Graduate => guild.alumni,
+	};
+
+	// Someone may have sorted themselves out since we warned them.
+	let mut member = match guild.id.member(ctx, uid).await {
+		Ok(member) if member.roles.contains(&guild.member) => member,
+		_ => {
+			db.lock().unwrap().execute("UPDATE rollover_pending SET applied = 1 WHERE user_id = ?1 AND term = ?2", params![uid.0, term]).unwrap();
+			return true;
+		}
+	};
+
+	let ok = member_change_role(ctx, db, &mut member, role, &guild.role_list(RoleList::Memberships)).await;
+
+	let db = db.lock().unwrap();
+	if ok {
+		db.execute("UPDATE rollover_pending SET applied = 1 WHERE user_id = ?1 AND term = ?2", params![uid.0, term]).unwrap();
+		audit(&db, "rollover", Some(uid), &format!("rollover-{}", action.as_str()), &format!("{}: {}", term, member.user.tag()));
+	}
+
+	ok
+}
+
+/// Contested changes still waiting on an officer, as (user, term, what the change is).
+pub fn contested(db: &Connection) -> Vec<(UserId, String, &'static str)> {
+	let mut stmt = db.prepare("SELECT user_id, term, action FROM rollover_pending WHERE contested = 1 AND applied = 0 ORDER BY term, user_id").unwrap();
+	let rows = stmt.query_map([], |r| Ok((UserId(r.get(0)?), r.get::<_, String>(1)?, r.get::<_, String>(2)?))).unwrap();
+
+	rows.filter_map(|x| x.ok())
+		.filter_map(|(user, term, action)| Some((user, term, Action::from_str(&action)?.describe())))
+		.collect()
+}
+
+/// Settle a member's contested changes: either make them after all, or drop them so the member keeps
+/// their membership. Returns how many were settled, and how many of those failed to apply.
+pub async fn resolve(ctx: &Context, guild: &GuildConfig, user: UserId, apply: bool, actor: &str) -> (usize, usize) {
+	let db = db(ctx).await;
+
+	let pending: Vec<(String, String)> = {
+		let db = db.lock().unwrap();
+		let mut stmt = db.prepare("SELECT term, action FROM rollover_pending WHERE user_id = ?1 AND contested = 1 AND applied = 0").unwrap();
+		let rows = stmt.query_map([user.0], |r| Ok((r.get(0)?, r.get(1)?))).unwrap();
+		rows.filter_map(|x| x.ok()).collect()
+	};
+
+	let mut failed = 0;
+	for (term, action) in &pending {
+		if apply {
+			db.lock().unwrap().execute("UPDATE rollover_pending SET contested = 0 WHERE user_id = ?1 AND term = ?2", params![user.0, term]).unwrap();
+
+			// Left uncontested, a failed change is retried with the rest of the rollover.
+			let done = match Action::from_str(action) {
+				Some(action) => apply_change(ctx, guild, &db, user, term, action).await,
+				None => false,
+			};
+			if !done {
+				failed += 1;
+			}
+		} else {
+			db.lock().unwrap().execute("DELETE FROM rollover_pending WHERE user_id = ?1 AND term = ?2", params![user.0, term]).unwrap();
+		}
+
+		let outcome = if apply { "applied" } else { "dismissed" };
+		audit(&db.lock().unwrap(), actor, Some(user), "rollover-resolve", &format!("{}: {}", term, outcome));
+	}
+
+	(pending.len(), failed)
+}
+
+/// Someone clicked "This is a mistake" on a rollover DM: hold their change for an officer.
+pub async fn handle_contest(ctx: Context, component: MessageComponentInteraction) {
+	let term = component.data.custom_id.strip_prefix("contest-rollover:").unwrap_or_default().to_string();
+	let user = &component.user;
+
+	let contested = {
+		let db = db(&ctx).await;
+		let db = db.lock().unwrap();
+
+		let changed = db.execute(
+			"UPDATE rollover_pending SET contested = 1 WHERE user_id = ?1 AND term = ?2 AND applied = 0",
+			params![user.id.0, term],
+		).unwrap_or(0);
+
+		if changed > 0 {
+			audit(&db, &user.tag(), Some(user.id), "rollover-contest", &term);
+		}
+
+		changed > 0
+	};
+
+	let reply = if contested {
+		"Thanks! Your membership won't change until an officer has taken a look."
+	} else {
+		"Your membership has already been updated; please reach out to an officer."
+	};
+
+	component.create_interaction_response(&ctx, |r| {
+		r.kind(InteractionResponseType::UpdateMessage);
+		r.interaction_response_data(|d| d.content(reply).components(|c| c))
+	}).await.unwrap();
+
+	if let (true, Some(chan)) = (contested, rollover_guild().and_then(|x| x.officer_channel)) {
+		let sent = chan.say(&ctx, format!("<@{}> contested their {} membership rollover. Settle it with /rollover resolve.", user.id, term)).await;
+		if sent.is_err() {
+			error!("Error notifying officers of {}'s contested rollover", user.tag());
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::bot::db::open_memory;
+
+	#[test]
+	fn contested_lists_only_unsettled_changes() {
+		let db = open_memory();
+		db.execute_batch("
+			INSERT INTO rollover_pending (user_id, term, action, contested, applied) VALUES (1, 'Fall 2026', 'lapse', 1, 0);
+			INSERT INTO rollover_pending (user_id, term, action, contested, applied) VALUES (2, 'Fall 2026', 'graduate', 0, 0);
+			INSERT INTO rollover_pending (user_id, term, action, contested, applied) VALUES (3, 'Fall 2026', 'lapse', 1, 1);
+		").unwrap();
+
+		assert_eq!(contested(&db), vec![(UserId(1), "Fall 2026".to_string(), Action::Lapse.describe())]);
+	}
+
+	#[test]
+	fn terms_order_by_year_then_season() {
+		assert!(parse_term("Fall 2026") < parse_term("Spring 2027"));
+		assert!(parse_term("Spring 2026") < parse_term("fall 2026"));
+		assert_eq!(parse_term("Winter 2026"), None);
+	}
+
+	#[test]
+	fn term_transitions() {
+		let db = open_memory();
+		db.execute_batch("
+			INSERT INTO graduations (user_id, term) VALUES (1, 'Spring 2026');
+			INSERT INTO graduations (user_id, term) VALUES (2, 'Fall 2026');
+			INSERT INTO dues (user_id, term) VALUES (1, 'Fall 2026');
+			INSERT INTO dues (user_id, term) VALUES (3, 'Fall 2026');
+			INSERT INTO dues (user_id, term) VALUES (4, 'Spring 2026');
+		").unwrap();
+
+		// Graduated last term, even though dues were paid
+		assert_eq!(action_for(&db, 1, "Fall 2026"), Some(Action::Graduate));
+		// Graduating this term, without dues
+		assert_eq!(action_for(&db, 2, "Fall 2026"), Some(Action::Lapse));
+		assert_eq!(action_for(&db, 3, "Fall 2026"), None);
+		// Dues from an earlier term don't carry over
+		assert_eq!(action_for(&db, 4, "Fall 2026"), Some(Action::Lapse));
+		assert_eq!(action_for(&db, 5, "Fall 2026"), Some(Action::Lapse));
+	}
+}
\ No newline at end of file
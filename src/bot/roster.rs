@@ -0,0 +1,369 @@
+use std::sync::Mutex;
+
+use chrono::{Datelike, Local, NaiveDate};
+use rusqlite::{Connection, params};
+use serde::Serialize;
+use serenity::http::{CacheHttp, Http};
+use serenity::model::channel::GuildChannel;
+use serenity::model::guild::{Member, PartialGuild};
+use serenity::model::id::{GuildId, RoleId, UserId};
+use serenity::model::Permissions;
+
+use crate::bot::config::{FALL_START_MONTH, GuildConfig, RoleList};
+use crate::bot::mc::utils::{chan_joinable, member_change_role};
+
+/// Column names we'll take a roster row's Discord username or ID from, in order of preference.
+/// If none are present, the first column is used.
+const KEY_COLUMNS: &[&str] = &["discord", "discord_id", "username", "id"];
+
+/// One row of the treasurer's roster of paid members.
+pub struct RosterRow {
+	/// The member's Discord username (with or without a discriminator) or user ID.
+	pub key: String,
+	/// The term they graduate, like "Spring 2027", if the roster has a `graduation` column.
+	pub graduation: Option<String>,
+}
+
+/// What importing a roster would do.
+pub struct ImportPlan {
+	/// Matched members who will be given the guild's member role.
+	pub promote: Vec<Member>,
+	/// Matched members who are already members.
+	pub unchanged: Vec<Member>,
+	/// Rows that didn't match anyone in the guild.
+	pub unmatched: Vec<String>,
+	/// Graduation terms to record for matched members.
+	pub graduations: Vec<(UserId, String)>,
+}
+
+/// The term a date falls in, like "Fall 2026".
+pub fn term_for(date: NaiveDate) -> String {
+	let season = if date.month() >= FALL_START_MONTH { "Fall" } else { "Spring" };
+	format!("{} {}", season, date.year())
+}
+
+/// The term dues paid right now count toward.
+pub fn current_term() -> String {
+	term_for(Local::now().date_naive())
+}
+
+pub fn parse_roster(data: &[u8]) -> Result<Vec<RosterRow>, csv::Error> {
+	let mut reader = csv::ReaderBuilder::new().flexible(true).from_reader(data);
+
+	let headers: Vec<String> = reader.headers()?.iter().map(|x| x.trim().to_lowercase()).collect();
+	let key_col = KEY_COLUMNS.iter()
+		.find_map(|k| headers.iter().position(|x| x == k))
+		.unwrap_or(0);
+	let grad_col = headers.iter().position(|x| x == "graduation");
+
+	let mut rows = vec![];
+	for record in reader.records() {
+		let record = record?;
+
+		let key = match record.get(key_col).map(|x| x.trim()) {
+			Some(key) if !key.is_empty() => key.to_string(),
+			_ => continue,
+		};
+
+		let graduation = grad_col
+			.and_then(|x| record.get(x))
+			.map(|x| x.trim().to_string())
+			.filter(|x| !x.is_empty());
+
+		rows.push(RosterRow { key, graduation });
+	}
+
+	Ok(rows)
+}
+
+/// Every member of the guild, fetched over HTTP so it works without a gateway connection.
+pub async fn fetch_members(http: &Http, guild: GuildId) -> serenity::Result<Vec<Member>> {
+	let mut members = vec![];
+	let mut after = None;
+
+	loop {
+		let page = guild.members(http, Some(1000), after).await?;
+		after = page.last().map(|x| x.user.id);
+		let done = page.len() < 1000;
+		members.extend(page);
+
+		if done {
+			return Ok(members);
+		}
+	}
+}
+
+fn matches(key: &str, member: &Member) -> bool {
+	let key = key.strip_prefix('@').unwrap_or(key);
+
+	if let Ok(id) = key.parse::<u64>() {
+		return member.user.id.0 == id;
+	}
+
+	let user = &member.user;
+	key.eq_ignore_ascii_case(&user.name) || key.eq_ignore_ascii_case(&format!("{}#{:04}", user.name, user.discriminator))
+}
+
+pub fn plan_import(guild: &GuildConfig, rows: &[RosterRow], members: &[Member]) -> ImportPlan {
+	let mut plan = ImportPlan {
+		promote: vec![],
+		unchanged: vec![],
+		unmatched: vec![],
+		graduations: vec![],
+	};
+
+	for row in rows {
+		let member = match members.iter().find(|x| matches(&row.key, x)) {
+			Some(member) => member,
+			None => {
+				plan.unmatched.push(row.key.clone());
+				continue;
+			}
+		};
+
+		// Rosters sometimes list the same person twice.
+		if plan.promote.iter().chain(&plan.unchanged).any(|x| x.user.id == member.user.id) {
+			continue;
+		}
+
+		if let Some(grad) = &row.graduation {
+			plan.graduations.push((member.user.id, grad.clone()));
+		}
+
+		if member.roles.contains(&guild.member) {
+			plan.unchanged.push(member.clone());
+		} else {
+			plan.promote.push(member.clone());
+		}
+	}
+
+	plan
+}
+
+impl ImportPlan {
+	/// A human-readable summary of the plan, listing at most `max` names per section.
+	pub fn preview(&self, max: usize) -> String {
+		let mut out = format!(
+			"{} to promote to Member, {} already Members, {} unmatched.\n",
+			self.promote.len(), self.unchanged.len(), self.unmatched.len()
+		);
+
+		let promote: Vec<_> = self.promote.iter().map(|x| x.user.tag()).collect();
+		out.push_str(&list_section("Promote", &promote, max));
+		out.push_str(&list_section("Unmatched", &self.unmatched, max));
+
+		out
+	}
+}
+
+fn list_section(title: &str, items: &[String], max: usize) -> String {
+	if items.is_empty() {
+		return String::new();
+	}
+
+	let mut out = format!("\n**{}:**\n", title);
+	for item in items.iter().take(max) {
+		out.push_str(&format!("- {}\n", item));
+	}
+	if items.len() > max {
+		out.push_str(&format!("...and {} more\n", items.len() - max));
+	}
+
+	out
+}
+
+/// Promote everyone the plan says to, and record dues for `term` and graduation terms for every
+/// matched member. Returns how many promotions succeeded and failed.
+pub async fn apply_import(cache_http: impl CacheHttp, db: &Mutex<Connection>, guild: &GuildConfig, plan: &ImportPlan, term: &str) -> (usize, usize) {
+	let (mut ok, mut failed) = (0, 0);
+
+	for member in &plan.promote {
+		let mut member = member.clone();
+		if member_change_role(&cache_http, db, &mut member, guild.member, &guild.role_list(RoleList::Memberships)).await {
+			ok += 1;
+		} else {
+			failed += 1;
+		}
+	}
+
+	let db = db.lock().unwrap();
+
+	for member in plan.promote.iter().chain(&plan.unchanged) {
+		if db.execute("INSERT OR IGNORE INTO dues (user_id, term) VALUES (?1, ?2)", params![member.user.id.0, term]).is_err() {
+			error!("Error recording {} dues for user {}", term, member.user.tag());
+		}
+	}
+
+	for (user, grad) in &plan.graduations {
+		if db.execute("INSERT OR REPLACE INTO graduations (user_id, term) VALUES (?1, ?2)", params![user.0, grad]).is_err() {
+			error!("Error recording graduation term for UserId {}", user);
+		}
+	}
+
+	info!("Imported roster for {}: {} promoted, {} failed, {} unmatched", term, ok, failed, plan.unmatched.len());
+
+	(ok, failed)
+}
+
+/// One guild member's row in a roster export.
+#[derive(Serialize)]
+pub struct ExportRow {
+	/// A string, since JSON numbers can't hold a snowflake exactly.
+	pub id: String,
+	pub username: String,
+	pub nickname: Option<String>,
+	/// Their membership role, if any.
+	pub membership: Option<String>,
+	/// Their project roles.
+	pub projects: Vec<String>,
+	/// Their self-assignable roles.
+	pub roles: Vec<String>,
+	/// The joinable channels they can see.
+	pub channels: Vec<String>,
+}
+
+/// Whether the member can see a channel; the same check as `user_in_chan`, without needing a cache.
+fn member_in_chan(guild: &PartialGuild, channel: &GuildChannel, member: &Member) -> bool {
+	guild.user_permissions_in(channel, member)
+		.map(|p| p.contains(Permissions::VIEW_CHANNEL))
+		.unwrap_or(false)
+}
+
+/// Build an export row for every guild member, or only those on `project` if given. Everything is
+/// fetched over HTTP so this works without a gateway connection.
+pub async fn build_export(http: &Http, config: &GuildConfig, project: Option<RoleId>) -> serenity::Result<Vec<ExportRow>> {
+	let guild = config.id.to_partial_guild(http).await?;
+	let members = fetch_members(http, config.id).await?;
+
+	let mut chans: Vec<_> = config.id.channels(http).await?
+		.into_values()
+		.filter(chan_joinable)
+		.collect();
+	chans.sort_by_key(|x| x.position);
+
+	let role_names = |allowed: &[RoleId], member: &Member| -> Vec<String> {
+		allowed.iter()
+			.filter(|x| member.roles.contains(x))
+			.map(|x| guild.roles.get(x).map(|r| r.name.clone()).unwrap_or_else(|| x.to_string()))
+			.collect()
+	};
+
+	let mut rows: Vec<_> = members.iter()
+		.filter(|x| !x.user.bot)
+		.filter(|x| project.map(|p| x.roles.contains(&p)).unwrap_or(true))
+		.map(|member| ExportRow {
+			id: member.user.id.to_string(),
+			username: member.user.tag(),
+			nickname: member.nick.clone(),
+			membership: role_names(&config.role_list(RoleList::Memberships), member).into_iter().next(),
+			projects: role_names(&config.role_list(RoleList::Projects), member),
+			roles: role_names(&config.role_list(RoleList::Roles), member),
+			channels: chans.iter()
+				.filter(|x| member_in_chan(&guild, x, member))
+				.map(|x| x.name.clone())
+				.collect(),
+		})
+		.collect();
+
+	rows.sort_by_key(|x| x.username.to_lowercase());
+
+	Ok(rows)
+}
+
+/// Render an export as CSV, joining list columns with semicolons.
+pub fn export_csv(rows: &[ExportRow]) -> Vec<u8> {
+	let mut writer = csv::Writer::from_writer(vec![]);
+
+	writer.write_record(["id", "username", "nickname", "membership", "projects", "roles", "channels"]).unwrap();
+	for row in rows {
+		writer.write_record([
+			row.id.as_str(),
+			row.username.as_str(),
+			row.nickname.as_deref().unwrap_or(""),
+			row.membership.as_deref().unwrap_or(""),
+			row.projects.join("; ").as_str(),
+			row.roles.join("; ").as_str(),
+			row.channels.join("; ").as_str(),
+		]).unwrap();
+	}
+
+	writer.into_inner().unwrap()
+}
+
+pub fn export_json(rows: &[ExportRow]) -> Vec<u8> {
+	serde_json::to_vec_pretty(rows).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::bot::config::ROLLOVER_DATES;
+
+	fn date(month: u32, day: u32) -> NaiveDate {
+		NaiveDate::from_ymd_opt(2026, month, day).unwrap()
+	}
+
+	#[test]
+	fn term_for_boundaries() {
+		assert_eq!(term_for(date(1, 1)), "Spring 2026");
+		assert_eq!(term_for(date(FALL_START_MONTH - 1, 31)), "Spring 2026");
+		assert_eq!(term_for(date(FALL_START_MONTH, 1)), "Fall 2026");
+		assert_eq!(term_for(date(12, 31)), "Fall 2026");
+	}
+
+	#[test]
+	fn rollovers_start_their_term() {
+		assert_eq!(term_for(date(ROLLOVER_DATES[0].0, ROLLOVER_DATES[0].1)), "Spring 2026");
+		assert_eq!(term_for(date(ROLLOVER_DATES[1].0, ROLLOVER_DATES[1].1)), "Fall 2026");
+	}
+
+	#[test]
+	fn roster_key_column() {
+		let rows = parse_roster(b"Name,Discord,Graduation\nAda,ada#0001,Spring 2027\nBob, 1234 ,\nEve,,Fall 2026\n").unwrap();
+
+		assert_eq!(rows.len(), 2);
+		assert_eq!(rows[0].key, "ada#0001");
+		assert_eq!(rows[0].graduation.as_deref(), Some("Spring 2027"));
+		assert_eq!(rows[1].key, "1234");
+		assert_eq!(rows[1].graduation, None);
+	}
+
+	#[test]
+	fn roster_without_key_column_uses_first() {
+		let rows = parse_roster(b"handle,paid\nada,yes\n").unwrap();
+
+		assert_eq!(rows.len(), 1);
+		assert_eq!(rows[0].key, "ada");
+		assert_eq!(rows[0].graduation, None);
+	}
+
+	fn export_row() -> ExportRow {
+		ExportRow {
+			id: "491275273598402561".to_string(),
+			username: "ada#0001".to_string(),
+			nickname: None,
+			membership: Some("Member".to_string()),
+			projects: vec!["Rocketry".to_string(), "CubeSat".to_string()],
+			roles: vec![],
+			channels: vec!["general".to_string()],
+		}
+	}
+
+	#[test]
+	fn export_csv_joins_lists() {
+		let csv = String::from_utf8(export_csv(&[export_row()])).unwrap();
+		let lines: Vec<_> = csv.lines().collect();
+
+		assert_eq!(lines[0], "id,username,nickname,membership,projects,roles,channels");
+		assert_eq!(lines[1], "491275273598402561,ada#0001,,Member,Rocketry; CubeSat,,general");
+	}
+
+	#[test]
+	fn export_json_keeps_ids_as_strings() {
+		let json: serde_json::Value = serde_json::from_slice(&export_json(&[export_row()])).unwrap();
+
+		assert_eq!(json[0]["id"], "491275273598402561");
+		assert_eq!(json[0]["nickname"], serde_json::Value::Null);
+		assert_eq!(json[0]["projects"][1], "CubeSat");
+	}
+}
\ No newline at end of file
@@ -0,0 +1,396 @@
+use std::collections::HashSet;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use cron::Schedule;
+use rusqlite::{Connection, OptionalExtension, params};
+use serenity::client::Context;
+use serenity::model::id::GuildId;
+use tokio::sync::watch;
+use tokio::task::JoinSet;
+
+use crate::bot::config::SCHEDULED_JOBS;
+use crate::bot::db::db;
+use crate::bot::inactivity;
+use crate::bot::rollover;
+
+/// How often we look for due jobs.
+const POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+/// A job as stored in the database, for status reports.
+pub struct Job {
+	pub name: String,
+	/// A cron expression, or `None` for a one-shot job.
+	pub schedule: Option<String>,
+	pub next_run: i64,
+	pub last_run: Option<i64>,
+	pub last_status: Option<String>,
+}
+
+/// Runs cron-like and one-shot jobs in the background. Jobs live in SQLite, so they survive restarts.
+pub struct Scheduler {
+	/// Flipped to true to ask the loop to stop.
+	cancel: watch::Sender<bool>,
+	/// The loop's task, once started.
+	handle: tokio::sync::Mutex<Option<tokio::task::JoinHandle<()>>>,
+	/// Jobs currently running, so a slow job isn't started twice.
+	running: Arc<Mutex<HashSet<String>>>,
+}
+
+/// Run the task a job names. This is the one place jobs get their behavior. A job's payload is the
+/// ID of the guild it's for, or empty for every guild.
+async fn run_task(ctx: &Context, task: &str, payload: &str) -> Result<(), String> {
+	let guild = match payload {
+		"" => None,
+		_ => Some(payload.parse::<u64>().map(GuildId).map_err(|_| format!("Invalid guild {}", payload))?),
+	};
+
+	match task {
+		"rollover" => {
+			rollover::tick(ctx, guild).await;
+			Ok(())
+		}
+		"inactive-channels" => {
+			inactivity::scan(ctx, guild).await;
+			Ok(())
+		}
+		_ => Err(format!("Unknown task {}", task)),
+	}
+}
+
+/// Every task name `run_task` understands.
+pub const TASKS: &[&str] = &["rollover", "inactive-channels"];
+
+/// When a cron schedule next fires after `after`.
+fn next_cron_run(schedule: &str, after: DateTime<Utc>) -> Option<i64> {
+	Schedule::from_str(schedule).ok()?.after(&after).next().map(|x| x.timestamp())
+}
+
+impl Default for Scheduler {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+impl Scheduler {
+	pub fn new() -> Self {
+		let (cancel, _) = watch::channel(false);
+
+		Self {
+			cancel,
+			handle: tokio::sync::Mutex::new(None),
+			running: Arc::new(Mutex::new(HashSet::new())),
+		}
+	}
+
+	/// Register the built-in jobs and start the loop. `ready` fires again on every reconnect, so
+	/// this only does anything the first time.
+	pub async fn start(&self, ctx: Context) {
+		let mut handle = self.handle.lock().await;
+		if handle.is_some() {
+			return;
+		}
+
+		{
+			let db = db(&ctx).await;
+			let db = db.lock().unwrap();
+
+			for (name, schedule) in SCHEDULED_JOBS {
+				let next_run = match next_cron_run(schedule, Utc::now()) {
+					Some(next_run) => next_run,
+					None => {
+						error!("Invalid cron schedule \"{}\" for job {}", schedule, name);
+						continue;
+					}
+				};
+
+				// Keep the existing next run unless the schedule itself changed.
+				if db.execute("
+					INSERT INTO jobs (name, schedule, task, payload, next_run) VALUES (?1, ?2, ?1, '', ?3)
+					ON CONFLICT (name) DO UPDATE SET next_run = excluded.next_run, schedule = excluded.schedule
+					WHERE schedule IS NOT excluded.schedule
+				", params![name, schedule, next_run]).is_err() {
+					error!("Error registering job {}", name);
+				}
+			}
+		}
+
+		let mut cancel = self.cancel.subscribe();
+		let running = self.running.clone();
+
+		*handle = Some(tokio::spawn(async move {
+			let mut jobs = JoinSet::new();
+			let mut interval = tokio::time::interval(POLL_INTERVAL);
+
+			loop {
+				tokio::select! {
+					_ = interval.tick() => {}
+					_ = cancel.changed() => break,
+				}
+
+				// Reap finished jobs so the set doesn't grow forever.
+				while jobs.try_join_next().is_some() {}
+
+				for (name, task, payload) in due_jobs(&*db(&ctx).await, &running) {
+					let ctx = ctx.clone();
+					let job = RunningJob { name, running: running.clone(), db: db(&ctx).await };
+
+					jobs.spawn(async move {
+						debug!("Running job {}", job.name);
+						let result = run_task(&ctx, &task, &payload).await;
+
+						let status = match &result {
+							Ok(_) => "ok".to_string(),
+							Err(why) => {
+								error!("Job {} failed: {}", job.name, why);
+								format!("error: {}", why)
+							}
+						};
+
+						finish_job(&job.db.lock().unwrap(), &job.name, &status);
+					});
+				}
+			}
+
+			// One-shot jobs cut off here run again after a restart, since they're only removed
+			// once they finish. Cron jobs had their next run moved forward when they were claimed,
+			// so they wait for their next slot instead.
+			jobs.shutdown().await;
+			debug!("Scheduler stopped");
+		}));
+
+		info!("Scheduler started");
+	}
+
+	/// Stop the loop and cancel any running jobs.
+	pub async fn shutdown(&self) {
+		self.cancel.send_replace(true);
+
+		if let Some(handle) = self.handle.lock().await.take() {
+			if handle.await.is_err() {
+				error!("Scheduler loop panicked");
+			}
+		}
+	}
+
+	/// Is this job running right now?
+	pub fn is_running(&self, name: &str) -> bool {
+		self.running.lock().unwrap().contains(name)
+	}
+}
+
+/// A claimed job, taken off the running list once it's dropped. That happens even if the job
+/// panics, in which case it's also recorded as failed, so it's neither stuck nor retried forever.
+struct RunningJob {
+	name: String,
+	running: Arc<Mutex<HashSet<String>>>,
+	db: Arc<Mutex<Connection>>,
+}
+
+impl Drop for RunningJob {
+	fn drop(&mut self) {
+		if std::thread::panicking() {
+			error!("Job {} panicked", self.name);
+			if let Ok(db) = self.db.lock() {
+				finish_job(&db, &self.name, "error: panicked");
+			}
+		}
+
+		if let Ok(mut running) = self.running.lock() {
+			running.remove(&self.name);
+		}
+	}
+}
+
+/// Claim every due job that isn't already running. Cron jobs get their next run moved forward
+/// right away, so a job that takes longer than the poll interval isn't started twice.
+fn due_jobs(db: &Mutex<Connection>, running: &Mutex<HashSet<String>>) -> Vec<(String, String, String)> {
+	let db = db.lock().unwrap();
+	let mut running = running.lock().unwrap();
+
+	let mut stmt = match db.prepare("SELECT name, schedule, task, payload FROM jobs WHERE next_run <= ?1") {
+		Ok(stmt) => stmt,
+		Err(_) => {
+			error!("Error looking for due jobs");
+			return vec![];
+		}
+	};
+	let due: Vec<(String, Option<String>, String, String)> = match stmt.query_map([Utc::now().timestamp()], |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?, r.get(3)?))) {
+		Ok(rows) => rows.filter_map(|x| x.ok()).collect(),
+		Err(_) => {
+			error!("Error looking for due jobs");
+			return vec![];
+		}
+	};
+
+	let mut ret = vec![];
+	for (name, schedule, task, payload) in due {
+		if running.contains(&name) {
+			continue;
+		}
+
+		if let Some(schedule) = schedule {
+			let next_run = next_cron_run(&schedule, Utc::now()).unwrap_or(i64::MAX);
+
+			// Running it without moving its next run would start it again every poll.
+			if db.execute("UPDATE jobs SET next_run = ?1 WHERE name = ?2", params![next_run, name]).is_err() {
+				error!("Error scheduling the next run of job {}", name);
+				continue;
+			}
+		}
+
+		running.insert(name.clone());
+		ret.push((name, task, payload));
+	}
+
+	ret
+}
+
+/// Record how a job went. One-shot jobs are done after a single run, so they're removed.
+fn finish_job(db: &Connection, name: &str, status: &str) {
+	let now = Utc::now().timestamp();
+
+	let one_shot = db
+		.query_row("SELECT schedule IS NULL FROM jobs WHERE name = ?1", [name], |r| r.get::<_, bool>(0))
+		.optional()
+		.unwrap_or(None)
+		.unwrap_or(false);
+
+	if one_shot {
+		if db.execute("DELETE FROM jobs WHERE name = ?1", [name]).is_err() {
+			error!("Error removing finished one-shot job {}", name);
+		}
+		info!("One-shot job {} finished: {}", name, status);
+	} else if db.execute("UPDATE jobs SET last_run = ?1, last_status = ?2 WHERE name = ?3", params![now, status, name]).is_err() {
+		error!("Error recording how job {} went", name);
+	}
+}
+
+/// Queue a task to run once at `at` (a unix timestamp), for one guild. Returns the new job's name.
+pub fn schedule_once(db: &Connection, task: &str, guild: GuildId, at: i64) -> rusqlite::Result<String> {
+	let name = format!("{}-{}", task, rusty_ulid::Ulid::generate());
+
+	db.execute(
+		"INSERT INTO jobs (name, schedule, task, payload, next_run) VALUES (?1, NULL, ?2, ?3, ?4)",
+		params![name, task, guild.0.to_string(), at],
+	)?;
+
+	Ok(name)
+}
+
+/// Every job that acts on a guild, including the ones for every guild, soonest first.
+pub fn list_jobs(db: &Connection, guild: GuildId) -> Vec<Job> {
+	db.prepare("SELECT name, schedule, next_run, last_run, last_status FROM jobs WHERE payload IN ('', ?1) ORDER BY next_run").unwrap()
+		.query_map([guild.0.to_string()], |r| Ok(Job {
+			name: r.get(0)?,
+			schedule: r.get(1)?,
+			next_run: r.get(2)?,
+			last_run: r.get(3)?,
+			last_status: r.get(4)?,
+		})).unwrap()
+		.filter_map(|x| x.ok())
+		.collect()
+}
+
+/// Format a unix timestamp as a Discord timestamp, which renders in the reader's time zone.
+pub fn discord_time(ts: i64) -> String {
+	if ts == i64::MAX {
+		return "never".to_string();
+	}
+
+	format!("<t:{}:R>", ts)
+}
+
+#[cfg(test)]
+mod tests {
+	use chrono::TimeZone;
+
+	use super::*;
+	use crate::bot::db::open_memory;
+
+	#[test]
+	fn cron_next_run() {
+		// A Sunday afternoon.
+		let sunday = Utc.with_ymd_and_hms(2026, 10, 18, 16, 0, 0).unwrap();
+
+		let monday = Utc.with_ymd_and_hms(2026, 10, 19, 15, 0, 0).unwrap();
+		assert_eq!(next_cron_run("0 0 15 * * Mon", sunday), Some(monday.timestamp()));
+
+		let hour = Utc.with_ymd_and_hms(2026, 10, 18, 17, 0, 0).unwrap();
+		assert_eq!(next_cron_run("0 0 * * * *", sunday), Some(hour.timestamp()));
+
+		assert_eq!(next_cron_run("not a schedule", sunday), None);
+	}
+
+	fn next_run(db: &Mutex<Connection>, name: &str) -> i64 {
+		db.lock().unwrap().query_row("SELECT next_run FROM jobs WHERE name = ?1", [name], |r| r.get(0)).unwrap()
+	}
+
+	#[test]
+	fn due_jobs_claimed_once() {
+		let db = Mutex::new(open_memory());
+		let running = Mutex::new(HashSet::new());
+		let now = Utc::now().timestamp();
+
+		{
+			let db = db.lock().unwrap();
+			db.execute("INSERT INTO jobs (name, schedule, task, payload, next_run) VALUES ('cron', '0 0 * * * *', 'rollover', '', ?1)", [now - 60]).unwrap();
+			db.execute("INSERT INTO jobs (name, schedule, task, payload, next_run) VALUES ('later', NULL, 'rollover', '', ?1)", [now + 3600]).unwrap();
+			schedule_once(&db, "inactive-channels", GuildId(1), now).unwrap();
+		}
+
+		let mut due: Vec<_> = due_jobs(&db, &running).into_iter().map(|(_, task, payload)| (task, payload)).collect();
+		due.sort();
+		assert_eq!(due, vec![
+			("inactive-channels".to_string(), "1".to_string()),
+			("rollover".to_string(), "".to_string()),
+		]);
+
+		// Cron jobs move on to their next run as soon as they're claimed; one-shots stay put until
+		// they finish.
+		assert!(next_run(&db, "cron") > now);
+		assert_eq!(running.lock().unwrap().len(), 2);
+
+		// Still running, so nothing is claimed twice.
+		assert!(due_jobs(&db, &running).is_empty());
+	}
+
+	#[test]
+	fn one_shot_jobs_removed_when_finished() {
+		let db = open_memory();
+		let name = schedule_once(&db, "rollover", GuildId(1), 0).unwrap();
+
+		finish_job(&db, &name, "ok");
+		assert!(list_jobs(&db, GuildId(1)).is_empty());
+	}
+
+	#[test]
+	fn jobs_listed_per_guild() {
+		let db = open_memory();
+		db.execute("INSERT INTO jobs (name, schedule, task, payload, next_run) VALUES ('rollover', '0 0 * * * *', 'rollover', '', 0)", []).unwrap();
+		schedule_once(&db, "rollover", GuildId(1), 0).unwrap();
+		schedule_once(&db, "rollover", GuildId(2), 0).unwrap();
+
+		assert_eq!(list_jobs(&db, GuildId(1)).len(), 2);
+		assert_eq!(list_jobs(&db, GuildId(3)).len(), 1);
+	}
+
+	#[test]
+	fn panicking_jobs_are_cleared() {
+		let db = Arc::new(Mutex::new(open_memory()));
+		let name = schedule_once(&db.lock().unwrap(), "rollover", GuildId(1), 0).unwrap();
+		let running = Arc::new(Mutex::new(HashSet::from([name.clone()])));
+
+		let job = RunningJob { name, running: running.clone(), db: db.clone() };
+		let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(move || {
+			let _job = job;
+			panic!("job failed");
+		}));
+
+		assert!(result.is_err());
+		assert!(running.lock().unwrap().is_empty());
+		assert!(list_jobs(&db.lock().unwrap(), GuildId(1)).is_empty());
+	}
+}
\ No newline at end of file
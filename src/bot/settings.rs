@@ -0,0 +1,210 @@
+use std::collections::HashMap;
+use std::sync::{LazyLock, RwLock};
+
+use rusqlite::{Connection, params};
+use serenity::model::id::GuildId;
+
+use crate::bot::config::{AssignableKind, GuildConfig, RoleList};
+
+/// The changes made to each guild's settings, by guild and setting key, as (ID, included) pairs in
+/// the order they were made.
+type Overrides = HashMap<(GuildId, String), Vec<(u64, bool)>>;
+
+/// Loaded when the database is opened and kept in step with it after, so changes made through
+/// /mcadmin take effect immediately.
+static OVERRIDES: LazyLock<RwLock<Overrides>> = LazyLock::new(Default::default);
+
+/// One list officers can edit through /mcadmin. Changes are stored as additions and removals on
+/// top of the compiled-in defaults, so anything nobody has touched still follows the config.
+#[derive(Copy, Clone, PartialEq)]
+pub enum Setting {
+	Roles(RoleList),
+	/// The categories listed by the overwrite group at this index of the guild's `assignables`.
+	Categories(usize),
+	Excluded,
+}
+
+impl Setting {
+	/// Every setting a guild has, in menu order.
+	pub fn all(guild: &GuildConfig) -> Vec<Setting> {
+		let mut all = vec![
+			Setting::Roles(RoleList::Memberships),
+			Setting::Roles(RoleList::Roles),
+			Setting::Roles(RoleList::Projects),
+		];
+
+		for (idx, group) in guild.assignables.iter().enumerate() {
+			if let AssignableKind::ChannelOverwrite(_) = group.kind {
+				all.push(Setting::Categories(idx));
+			}
+		}
+
+		all.push(Setting::Excluded);
+		all
+	}
+
+	/// Find a guild's setting by its key.
+	pub fn from_key(guild: &GuildConfig, key: &str) -> Option<Setting> {
+		Setting::all(guild).into_iter().find(|x| x.key(guild) == key)
+	}
+
+	/// What this setting is stored under; also the custom ID of its menu button.
+	pub fn key(&self, guild: &GuildConfig) -> String {
+		match self {
+			Setting::Roles(RoleList::Memberships) => "memberships".to_string(),
+			Setting::Roles(RoleList::Roles) => "roles".to_string(),
+			Setting::Roles(RoleList::Projects) => "projects".to_string(),
+			Setting::Categories(idx) => format!("categories:{}", guild.assignables[*idx].id),
+			Setting::Excluded => "excluded".to_string(),
+		}
+	}
+
+	pub fn name(&self, guild: &GuildConfig) -> String {
+		match self {
+			Setting::Roles(RoleList::Memberships) => "Memberships".to_string(),
+			Setting::Roles(RoleList::Roles) => "Roles".to_string(),
+			Setting::Roles(RoleList::Projects) => "Projects".to_string(),
+			Setting::Categories(idx) => format!("{} Categories", guild.assignables[*idx].name),
+			Setting::Excluded => "Excluded Channels".to_string(),
+		}
+	}
+
+	/// The select menu placeholders for adding and removing an entry.
+	pub fn placeholders(&self) -> (&'static str, &'static str) {
+		match self {
+			Setting::Roles(_) => ("Select a role to add...", "Select a role to remove..."),
+			Setting::Categories(_) => ("Select a category to list...", "Select a category to stop listing..."),
+			Setting::Excluded => ("Select a channel to exclude...", "Select a channel to stop excluding..."),
+		}
+	}
+
+	/// The IDs currently in this setting, overrides included.
+	pub fn values(&self, guild: &GuildConfig) -> Vec<u64> {
+		match self {
+			Setting::Roles(list) => guild.role_list(*list).into_iter().map(|x| x.0).collect(),
+			Setting::Categories(idx) => guild.categories(&guild.assignables[*idx]).into_iter().map(|x| x.0).collect(),
+			Setting::Excluded => guild.excluded_channels().into_iter().map(|x| x.0).collect(),
+		}
+	}
+
+	/// How to mention one of this setting's IDs in a message.
+	pub fn mention(&self, id: u64) -> String {
+		match self {
+			Setting::Roles(_) => format!("<@&{}>", id),
+			Setting::Categories(_) | Setting::Excluded => format!("<#{}>", id),
+		}
+	}
+}
+
+/// Read every override from the database. Called whenever it's opened.
+pub fn load(db: &Connection) {
+	let mut stmt = db.prepare("SELECT guild_id, setting, value, included FROM setting_overrides ORDER BY rowid").unwrap();
+	let rows = stmt.query_map([], |r| Ok((GuildId(r.get(0)?), r.get(1)?, r.get(2)?, r.get(3)?))).unwrap();
+
+	let mut overrides = OVERRIDES.write().unwrap();
+	overrides.clear();
+	for (guild, key, value, included) in rows.filter_map(|x| x.ok()) {
+		overrides.entry((guild, key)).or_default().push((value, included));
+	}
+}
+
+/// Add an ID to or remove one from a setting, returning whether it was saved.
+pub fn set(db: &Connection, guild: GuildId, key: &str, value: u64, included: bool) -> bool {
+	// Replacing the row gives it a new rowid, so `load` replays changes in the order they were made.
+	if db.execute(
+		"INSERT OR REPLACE INTO setting_overrides (guild_id, setting, value, included) VALUES (?1, ?2, ?3, ?4)",
+		params![guild.0, key, value, included],
+	).is_err() {
+		error!("Error saving setting {} for GuildId {}", key, guild);
+		return false;
+	}
+
+	let mut overrides = OVERRIDES.write().unwrap();
+	let changes = overrides.entry((guild, key.to_string())).or_default();
+	changes.retain(|(x, _)| *x != value);
+	changes.push((value, included));
+
+	true
+}
+
+/// Apply a setting's overrides to its compiled-in defaults.
+pub fn effective<T: Copy + From<u64> + Into<u64>>(guild: GuildId, key: &str, defaults: &[T]) -> Vec<T> {
+	let mut ids: Vec<u64> = defaults.iter().map(|x| (*x).into()).collect();
+
+	if let Some(changes) = OVERRIDES.read().unwrap().get(&(guild, key.to_string())) {
+		for (value, included) in changes {
+			if !included {
+				ids.retain(|x| x != value);
+			} else if !ids.contains(value) {
+				ids.push(*value);
+			}
+		}
+	}
+
+	ids.into_iter().map(T::from).collect()
+}
+
+#[cfg(test)]
+mod tests {
+	use std::sync::Mutex;
+
+	use serenity::model::id::RoleId;
+
+	use super::*;
+	use crate::bot::config::GUILDS;
+	use crate::bot::db::open_memory;
+
+	/// Overrides are global and `load` replaces them all, so tests take turns.
+	static LOCK: Mutex<()> = Mutex::new(());
+
+	#[test]
+	fn defaults_without_overrides() {
+		let _lock = LOCK.lock().unwrap();
+		assert_eq!(effective::<u64>(GuildId(100), "roles", &[1, 2]), vec![1, 2]);
+	}
+
+	#[test]
+	fn overrides_add_and_remove() {
+		let _lock = LOCK.lock().unwrap();
+		let db = open_memory();
+		let guild = GuildId(101);
+
+		assert!(set(&db, guild, "roles", 2, false));
+		assert!(set(&db, guild, "roles", 3, true));
+		assert!(set(&db, guild, "roles", 1, true));
+		assert_eq!(effective::<u64>(guild, "roles", &[1, 2]), vec![1, 3]);
+
+		// Other settings and guilds keep their defaults.
+		assert_eq!(effective::<u64>(guild, "projects", &[1, 2]), vec![1, 2]);
+		assert_eq!(effective::<u64>(GuildId(102), "roles", &[1, 2]), vec![1, 2]);
+	}
+
+	#[test]
+	fn latest_override_wins() {
+		let _lock = LOCK.lock().unwrap();
+		let db = open_memory();
+		let guild = GuildId(103);
+
+		assert!(set(&db, guild, "excluded", 5, true));
+		assert!(set(&db, guild, "excluded", 5, false));
+		assert_eq!(effective::<u64>(guild, "excluded", &[]), Vec::<u64>::new());
+
+		assert!(set(&db, guild, "excluded", 1, false));
+		assert!(set(&db, guild, "excluded", 1, true));
+		assert_eq!(effective::<u64>(guild, "excluded", &[1]), vec![1]);
+	}
+
+	#[test]
+	fn loaded_overrides_reach_role_lists() {
+		let _lock = LOCK.lock().unwrap();
+		let db = open_memory();
+		let guild = &GUILDS[0];
+		let key = Setting::Roles(RoleList::Projects).key(guild);
+
+		db.execute("INSERT INTO setting_overrides (guild_id, setting, value, included) VALUES (?1, ?2, 999, 1)", params![guild.id.0, key]).unwrap();
+		assert!(!guild.role_list(RoleList::Projects).contains(&RoleId(999)));
+
+		load(&db);
+		assert!(guild.role_list(RoleList::Projects).contains(&RoleId(999)));
+	}
+}
\ No newline at end of file
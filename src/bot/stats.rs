@@ -0,0 +1,224 @@
+use std::collections::BTreeMap;
+
+use chrono::{Datelike, Duration, Local, Months, NaiveDate, TimeZone, Utc};
+use rusqlite::{Connection, params};
+use serenity::client::Context;
+use serenity::model::guild::{Guild, Member};
+use serenity::model::id::{GuildId, RoleId};
+use serenity::model::Permissions;
+
+use crate::bot::config::{GuildConfig, RoleList};
+use crate::bot::db::db;
+use crate::bot::mc::utils::chan_joinable;
+
+/// How many weeks of MC sessions to show.
+const STATS_WEEKS: i64 = 8;
+
+/// How many months of growth to show.
+const STATS_MONTHS: u32 = 6;
+
+/// How many channels to show in the most and least joined lists.
+const STATS_TOP: usize = 5;
+
+/// A snapshot of how the server is used. Every list is a (label, count) pair.
+pub struct Stats {
+	pub memberships: Vec<(String, usize)>,
+	pub projects: Vec<(String, usize)>,
+	/// Every joinable channel by how many members can see it, most first.
+	pub channels: Vec<(String, usize)>,
+	/// MC sessions started each week, labelled by the week's Monday, oldest first.
+	pub sessions: Vec<(String, usize)>,
+	/// How many current members had joined the server by the end of each month, oldest first.
+	pub growth: Vec<(String, usize)>,
+}
+
+fn role_counts(guild: &Guild, roles: &[RoleId]) -> Vec<(String, usize)> {
+	roles.iter().map(|role| {
+		let name = guild.roles.get(role).map(|x| x.name.clone()).unwrap_or_else(|| role.to_string());
+		let count = guild.members.values().filter(|x| x.roles.contains(role)).count();
+		(name, count)
+	}).collect()
+}
+
+fn humans(guild: &Guild) -> impl Iterator<Item = &Member> {
+	guild.members.values().filter(|x| !x.user.bot)
+}
+
+/// When each of a guild's MC sessions since `since` started.
+fn session_starts(db: &Connection, guild: GuildId, since: i64) -> Vec<i64> {
+	let mut stmt = db.prepare("SELECT started_at FROM mc_sessions WHERE guild_id = ?1 AND started_at >= ?2").unwrap();
+	let rows = stmt.query_map(params![guild.0, since], |r| r.get(0)).unwrap();
+	rows.filter_map(|x| x.ok()).collect()
+}
+
+/// Gather stats from the guild cache and our session history.
+pub async fn gather(ctx: &Context, config: &GuildConfig) -> Option<Stats> {
+	let guild = ctx.cache.guild(config.id)?;
+	let chans = config.id.channels(ctx).await.ok()?;
+
+	let mut channels: Vec<_> = chans.values()
+		.filter(|x| chan_joinable(x))
+		.map(|chan| {
+			let count = humans(&guild)
+				.filter(|x| guild.user_permissions_in(chan, x).map(|p| p.contains(Permissions::VIEW_CHANNEL)).unwrap_or(false))
+				.count();
+			(chan.name.clone(), count)
+		})
+		.collect();
+	channels.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+
+	let today = Local::now().date_naive();
+	let monday = |x: NaiveDate| x - Duration::days(x.weekday().num_days_from_monday() as i64);
+
+	let mut weeks: BTreeMap<NaiveDate, usize> = (0..STATS_WEEKS)
+		.map(|x| (monday(today) - Duration::weeks(x), 0))
+		.collect();
+
+	let since = (monday(today) - Duration::weeks(STATS_WEEKS - 1)).and_hms_opt(0, 0, 0).unwrap();
+	let since = Local.from_local_datetime(&since).earliest().map(|x| x.timestamp()).unwrap_or(0);
+
+	let started = session_starts(&db(ctx).await.lock().unwrap(), config.id, since);
+
+	for ts in started {
+		if let Some(date) = Utc.timestamp_opt(ts, 0).single() {
+			if let Some(count) = weeks.get_mut(&monday(date.with_timezone(&Local).date_naive())) {
+				*count += 1;
+			}
+		}
+	}
+
+	// The first of each of the last few months, and the first of next month to close the last one.
+	let mut months = vec![];
+	let mut month = NaiveDate::from_ymd_opt(today.year(), today.month(), 1).unwrap();
+	for _ in 0..=STATS_MONTHS {
+		months.push(month);
+		month = month.checked_sub_months(Months::new(1)).unwrap();
+	}
+	months.reverse();
+
+	let joined: Vec<i64> = humans(&guild)
+		.filter_map(|x| x.joined_at.map(|t| t.unix_timestamp()))
+		.collect();
+
+	let growth = months.windows(2).map(|pair| {
+		let end = Local.from_local_datetime(&pair[1].and_hms_opt(0, 0, 0).unwrap()).earliest().map(|x| x.timestamp()).unwrap_or(0);
+		(pair[0].format("%Y-%m").to_string(), joined.iter().filter(|x| **x < end).count())
+	}).collect();
+
+	Some(Stats {
+		memberships: role_counts(&guild, &config.role_list(RoleList::Memberships)),
+		projects: role_counts(&guild, &config.role_list(RoleList::Projects)),
+		channels,
+		sessions: weeks.into_iter().map(|(k, v)| (k.format("%b %d").to_string(), v)).collect(),
+		growth,
+	})
+}
+
+fn lines(items: &[(String, usize)]) -> String {
+	if items.is_empty() {
+		return "None".to_string();
+	}
+
+	items.iter().map(|(name, count)| format!("{}: **{}**", name, count)).collect::<Vec<_>>().join("\n")
+}
+
+impl Stats {
+	/// The embed fields to show, as (title, body) pairs.
+	pub fn fields(&self) -> Vec<(&'static str, String)> {
+		let least: Vec<_> = self.channels.iter().rev().take(STATS_TOP).cloned().collect();
+
+		let mut growth = vec![];
+		let mut last = None;
+		for (month, count) in &self.growth {
+			let delta = last.map(|x: usize| format!(" (+{})", count.saturating_sub(x))).unwrap_or_default();
+			growth.push(format!("{}: **{}**{}", month, count, delta));
+			last = Some(*count);
+		}
+
+		vec![
+			("Memberships", lines(&self.memberships)),
+			("Projects", lines(&self.projects)),
+			("Most joined channels", lines(&self.channels[..self.channels.len().min(STATS_TOP)])),
+			("Least joined channels", lines(&least)),
+			("MC sessions per week", lines(&self.sessions)),
+			("Server growth", growth.join("\n")),
+		]
+	}
+
+	/// Every number as CSV rows of section, name and value.
+	pub fn csv(&self) -> Vec<u8> {
+		let mut writer = csv::Writer::from_writer(vec![]);
+
+		writer.write_record(["section", "name", "value"]).unwrap();
+		let sections = [
+			("membership", &self.memberships),
+			("project", &self.projects),
+			("channel", &self.channels),
+			("sessions_week", &self.sessions),
+			("growth_month", &self.growth),
+		];
+		for (section, items) in sections {
+			for (name, count) in items {
+				writer.write_record([section, name.as_str(), count.to_string().as_str()]).unwrap();
+			}
+		}
+
+		writer.into_inner().unwrap()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::bot::db::open_memory;
+
+	#[test]
+	fn sessions_counted_per_guild() {
+		let db = open_memory();
+		for (id, guild, at) in [("a", 1u64, 100i64), ("b", 1, 200), ("c", 2, 200), ("d", 1, 50)] {
+			db.execute("INSERT INTO mc_sessions (id, user_id, started_at, guild_id) VALUES (?1, 1, ?2, ?3)", params![id, at, guild]).unwrap();
+		}
+
+		let mut starts = session_starts(&db, GuildId(1), 100);
+		starts.sort();
+		assert_eq!(starts, vec![100, 200]);
+		assert_eq!(session_starts(&db, GuildId(2), 100), vec![200]);
+		assert!(session_starts(&db, GuildId(3), 0).is_empty());
+	}
+
+	fn counts(items: &[(&str, usize)]) -> Vec<(String, usize)> {
+		items.iter().map(|(name, count)| (name.to_string(), *count)).collect()
+	}
+
+	fn stats() -> Stats {
+		Stats {
+			memberships: counts(&[("Member", 40), ("Friend of SEDS", 12)]),
+			projects: vec![],
+			channels: counts(&[("a", 7), ("b", 6), ("c", 5), ("d", 4), ("e", 3), ("f", 2), ("g", 1)]),
+			sessions: counts(&[("2026-10-05", 3)]),
+			growth: counts(&[("Aug 2026", 50), ("Sep 2026", 58), ("Oct 2026", 58)]),
+		}
+	}
+
+	#[test]
+	fn fields_show_top_channels_and_growth() {
+		let fields = stats().fields();
+		let field = |title: &str| fields.iter().find(|x| x.0 == title).unwrap().1.clone();
+
+		assert_eq!(field("Projects"), "None");
+		assert_eq!(field("Most joined channels"), "a: **7**\nb: **6**\nc: **5**\nd: **4**\ne: **3**");
+		assert_eq!(field("Least joined channels"), "g: **1**\nf: **2**\ne: **3**\nd: **4**\nc: **5**");
+		assert_eq!(field("Server growth"), "Aug 2026: **50**\nSep 2026: **58** (+8)\nOct 2026: **58** (+0)");
+	}
+
+	#[test]
+	fn csv_has_every_number() {
+		let csv = String::from_utf8(stats().csv()).unwrap();
+		let lines: Vec<_> = csv.lines().collect();
+
+		assert_eq!(lines.len(), 1 + 2 + 7 + 1 + 3);
+		assert_eq!(lines[0], "section,name,value");
+		assert_eq!(lines[1], "membership,Member,40");
+		assert_eq!(lines[10], "sessions_week,2026-10-05,3");
+	}
+}
\ No newline at end of file
@@ -0,0 +1,335 @@
+use std::env;
+use std::io::{Read, Write};
+use std::net::{Ipv4Addr, SocketAddr, TcpStream};
+use std::process::exit;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use serenity::http::Http;
+
+use crate::bot;
+use crate::bot::config::{GUILDS, GuildConfig, RoleList, guild_config};
+use crate::bot::http::DEFAULT_ADDR;
+use crate::bot::roster::{apply_import, build_export, current_term, export_csv, export_json, fetch_members, parse_roster, plan_import};
+use crate::logging;
+
+const USAGE: &str = "Usage:
+	missioncontrol                 Run the bot
+	missioncontrol import <roster.csv> [--term <term>] [--dry-run] [--guild <id>]
+	                               Promote everyone on a roster of paid members
+	missioncontrol export [--format csv|json] [--project <name or id>] [--output <file>] [--guild <id>]
+	                               Export every member's membership, projects, roles and channels
+	missioncontrol healthcheck [--ready]
+	                               Exit 0 if the running bot is healthy (or ready), for Docker";
+
+/// Run a one-off admin task from the command line instead of the bot.
+pub async fn run(args: &[String]) {
+	match args[0].as_str() {
+		"import" => import(&args[1..]).await,
+		"export" => export(&args[1..]).await,
+		"healthcheck" => healthcheck(&args[1..]),
+		_ => {
+			eprintln!("{}", USAGE);
+			exit(2);
+		}
+	}
+}
+
+/// Set up logging and read the bot's token, which only the tasks that talk to Discord need.
+fn setup() -> String {
+	logging::setup();
+
+	match env::var("BOT_TOKEN") {
+		Ok(token) => token,
+		Err(_) => {
+			eprintln!("Error: BOT_TOKEN must be set");
+			exit(2);
+		}
+	}
+}
+
+/// Print why the arguments are wrong and exit.
+fn bad_args(why: String) -> ! {
+	eprintln!("{}", why);
+	exit(2);
+}
+
+struct ImportArgs {
+	path: String,
+	term: Option<String>,
+	dry_run: bool,
+	guild: &'static GuildConfig,
+}
+
+fn parse_import(args: &[String]) -> Result<ImportArgs, String> {
+	let mut path = None;
+	let mut term = None;
+	let mut dry_run = false;
+	let mut guild = &GUILDS[0];
+
+	let mut args = args.iter();
+	while let Some(arg) = args.next() {
+		match arg.as_str() {
+			"--dry-run" => dry_run = true,
+			"--guild" => guild = parse_guild(args.next())?,
+			"--term" => term = Some(args.next().ok_or(USAGE)?.clone()),
+			_ if arg.starts_with('-') || path.is_some() => return Err(USAGE.to_string()),
+			_ => path = Some(arg.clone()),
+		}
+	}
+
+	let path = path.ok_or(USAGE)?;
+	Ok(ImportArgs { path, term, dry_run, guild })
+}
+
+async fn import(args: &[String]) {
+	let ImportArgs { path, term, dry_run, guild } = parse_import(args).unwrap_or_else(|why| bad_args(why));
+	let term = term.unwrap_or_else(current_term);
+
+	// Opening the database loads the lists changed through /mcadmin, which planning looks at.
+	let db = Mutex::new(bot::db::open());
+
+	let rows = match std::fs::read(&path).map(|x| parse_roster(&x)) {
+		Ok(Ok(rows)) => rows,
+		Ok(Err(why)) => {
+			eprintln!("Error parsing {}: {}", path, why);
+			exit(1);
+		}
+		Err(why) => {
+			eprintln!("Error reading {}: {}", path, why);
+			exit(1);
+		}
+	};
+
+	let http = Http::new(&setup());
+
+	let members = match fetch_members(&http, guild.id).await {
+		Ok(members) => members,
+		Err(why) => {
+			eprintln!("Error retrieving guild members: {}", why);
+			exit(1);
+		}
+	};
+
+	let plan = plan_import(guild, &rows, &members);
+	println!("Roster import for {}\n{}", term, plan.preview(usize::MAX));
+
+	if dry_run {
+		println!("Dry run; nothing was changed.");
+		return;
+	}
+
+	let (ok, failed) = apply_import(&http, &db, guild, &plan, &term).await;
+
+	println!("Done! Promoted {} members ({} failed).", ok, failed);
+}
+
+struct ExportArgs {
+	json: bool,
+	project: Option<String>,
+	output: Option<String>,
+	guild: &'static GuildConfig,
+}
+
+fn parse_export(args: &[String]) -> Result<ExportArgs, String> {
+	let mut json = false;
+	let mut project = None;
+	let mut output = None;
+	let mut guild = &GUILDS[0];
+
+	let mut args = args.iter();
+	while let Some(arg) = args.next() {
+		match arg.as_str() {
+			"--format" => json = match args.next().map(|x| x.as_str()) {
+				Some("csv") => false,
+				Some("json") => true,
+				_ => return Err(USAGE.to_string()),
+			},
+			"--project" => project = Some(args.next().ok_or(USAGE)?.clone()),
+			"--output" => output = Some(args.next().ok_or(USAGE)?.clone()),
+			"--guild" => guild = parse_guild(args.next())?,
+			_ => return Err(USAGE.to_string()),
+		}
+	}
+
+	Ok(ExportArgs { json, project, output, guild })
+}
+
+async fn export(args: &[String]) {
+	let ExportArgs { json, project, output, guild } = parse_export(args).unwrap_or_else(|why| bad_args(why));
+
+	// Projects and roles may have been changed through /mcadmin or /project-admin, and opening the
+	// database loads those changes.
+	bot::db::open();
+
+	let http = Http::new(&setup());
+
+	// Projects can be given by name or role ID.
+	let project = match project {
+		None => None,
+		Some(project) => {
+			let roles = match guild.id.roles(&http).await {
+				Ok(roles) => roles,
+				Err(why) => {
+					eprintln!("Error retrieving guild roles: {}", why);
+					exit(1);
+				}
+			};
+
+			let found = guild.role_list(RoleList::Projects).into_iter()
+				.find(|x| x.to_string() == project || roles.get(x).map(|r| r.name.eq_ignore_ascii_case(&project)).unwrap_or(false));
+
+			match found {
+				Some(role) => Some(role),
+				None => {
+					eprintln!("Error: {} is not a project!", project);
+					exit(1);
+				}
+			}
+		}
+	};
+
+	let rows = match build_export(&http, guild, project).await {
+		Ok(rows) => rows,
+		Err(why) => {
+			eprintln!("Error building export: {}", why);
+			exit(1);
+		}
+	};
+
+	let data = if json { export_json(&rows) } else { export_csv(&rows) };
+
+	match output {
+		Some(path) => {
+			if let Err(why) = std::fs::write(&path, data) {
+				eprintln!("Error writing {}: {}", path, why);
+				exit(1);
+			}
+			eprintln!("Exported {} members to {}.", rows.len(), path);
+		}
+		None => {
+			std::io::stdout().write_all(&data).unwrap();
+		}
+	}
+}
+
+/// Look up a `--guild` argument among the configured guilds.
+fn parse_guild(arg: Option<&String>) -> Result<&'static GuildConfig, String> {
+	arg
+		.and_then(|x| x.parse::<u64>().ok())
+		.and_then(|x| guild_config(x.into()))
+		.ok_or_else(|| "Error: --guild must be the ID of a configured guild".to_string())
+}
+
+/// The endpoint `healthcheck` asks about.
+fn parse_healthcheck(args: &[String]) -> Result<&'static str, String> {
+	match args.iter().map(|x| x.as_str()).collect::<Vec<_>>()[..] {
+		[] => Ok("/healthz"),
+		["--ready"] => Ok("/readyz"),
+		_ => Err(USAGE.to_string()),
+	}
+}
+
+/// Ask the running bot's HTTP server whether it's healthy, exiting 0 if so.
+fn healthcheck(args: &[String]) {
+	let path = parse_healthcheck(args).unwrap_or_else(|why| bad_args(why));
+
+	let addr = env::var("HTTP_ADDR").unwrap_or_else(|_| DEFAULT_ADDR.to_string());
+	if addr.is_empty() {
+		println!("HTTP_ADDR is empty, so the HTTP server is off and there's nothing to check.");
+		exit(0);
+	}
+
+	let mut addr: SocketAddr = match addr.parse() {
+		Ok(addr) => addr,
+		Err(_) => {
+			eprintln!("HTTP_ADDR {} is not a valid address", addr);
+			exit(1);
+		}
+	};
+
+	// The server may listen on every interface, but we have to connect to one.
+	if addr.ip().is_unspecified() {
+		addr.set_ip(Ipv4Addr::LOCALHOST.into());
+	}
+
+	let timeout = Duration::from_secs(5);
+	let resp = TcpStream::connect_timeout(&addr, timeout).and_then(|mut stream| {
+		stream.set_read_timeout(Some(timeout))?;
+		write!(stream, "GET {} HTTP/1.0\r\nHost: {}\r\n\r\n", path, addr)?;
+
+		let mut resp = String::new();
+		stream.read_to_string(&mut resp)?;
+		Ok(resp)
+	});
+
+	let resp = match resp {
+		Ok(resp) => resp,
+		Err(why) => {
+			eprintln!("Error reaching http://{}{}: {}", addr, path, why);
+			exit(1);
+		}
+	};
+
+	let ok = resp.split_whitespace().nth(1) == Some("200");
+	println!("{}", resp.split("\r\n\r\n").nth(1).unwrap_or_default());
+
+	exit(if ok { 0 } else { 1 });
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn args(args: &[&str]) -> Vec<String> {
+		args.iter().map(|x| x.to_string()).collect()
+	}
+
+	#[test]
+	fn import_args() {
+		let guild = GUILDS[0].id.to_string();
+		let parsed = parse_import(&args(&["roster.csv", "--term", "Fall 2026", "--dry-run", "--guild", &guild])).unwrap();
+		assert_eq!(parsed.path, "roster.csv");
+		assert_eq!(parsed.term.as_deref(), Some("Fall 2026"));
+		assert!(parsed.dry_run);
+		assert_eq!(parsed.guild.id, GUILDS[0].id);
+
+		let parsed = parse_import(&args(&["roster.csv"])).unwrap();
+		assert_eq!(parsed.term, None);
+		assert!(!parsed.dry_run);
+	}
+
+	#[test]
+	fn import_rejects_bad_args() {
+		assert!(parse_import(&args(&[])).is_err());
+		assert!(parse_import(&args(&["--dryrun", "roster.csv"])).is_err());
+		assert!(parse_import(&args(&["roster.csv", "other.csv"])).is_err());
+		assert!(parse_import(&args(&["roster.csv", "--term"])).is_err());
+		assert!(parse_import(&args(&["roster.csv", "--guild", "1"])).is_err());
+	}
+
+	#[test]
+	fn export_args() {
+		let parsed = parse_export(&args(&[])).unwrap();
+		assert!(!parsed.json);
+		assert_eq!(parsed.project, None);
+		assert_eq!(parsed.output, None);
+
+		let parsed = parse_export(&args(&["--format", "json", "--project", "Rocketry", "--output", "out.json"])).unwrap();
+		assert!(parsed.json);
+		assert_eq!(parsed.project.as_deref(), Some("Rocketry"));
+		assert_eq!(parsed.output.as_deref(), Some("out.json"));
+
+		assert!(parse_export(&args(&["--format", "xml"])).is_err());
+		assert!(parse_export(&args(&["--output"])).is_err());
+		assert!(parse_export(&args(&["out.csv"])).is_err());
+	}
+
+	#[test]
+	fn healthcheck_args() {
+		assert_eq!(parse_healthcheck(&args(&[])), Ok("/healthz"));
+		assert_eq!(parse_healthcheck(&args(&["--ready"])), Ok("/readyz"));
+		assert!(parse_healthcheck(&args(&["--live"])).is_err());
+		assert!(parse_healthcheck(&args(&["--ready", "--ready"])).is_err());
+	}
+}
\ No newline at end of file
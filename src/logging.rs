@@ -0,0 +1,301 @@
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::future::Future;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use chrono::{Local, NaiveDate};
+use fern::colors::{Color, ColoredLevelConfig};
+use log::LevelFilter;
+use serde_json::{Map, Value};
+
+/// Who and what a log line is about. JSON log lines include these as fields.
+#[derive(Clone, Default)]
+pub struct LogContext {
+	/// The ULID of the MC session doing the logging.
+	pub session: Option<String>,
+	pub user: Option<String>,
+	pub guild: Option<String>,
+}
+
+tokio::task_local! {
+	static CONTEXT: LogContext;
+}
+
+/// Run `fut` with `context` attached to everything it logs.
+pub async fn with_context<F: Future>(context: LogContext, fut: F) -> F::Output {
+	CONTEXT.scope(context, fut).await
+}
+
+/// The context of whatever is logging right now, or an empty one outside `with_context`.
+pub fn context() -> LogContext {
+	CONTEXT.try_with(|x| x.clone()).unwrap_or_default()
+}
+
+#[derive(Copy, Clone)]
+enum Rotation {
+	Never,
+	Daily,
+	/// Rotate once the file reaches this many bytes.
+	Size(u64),
+}
+
+impl FromStr for Rotation {
+	type Err = String;
+
+	/// Parses "never", "daily", or a size like "10MB", "500KB" or "1048576".
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let s = s.trim().to_uppercase();
+		let (num, mult) = match s.as_str() {
+			"" | "NEVER" => return Ok(Rotation::Never),
+			"DAILY" => return Ok(Rotation::Daily),
+			_ if s.ends_with("GB") => (&s[..s.len() - 2], 1 << 30),
+			_ if s.ends_with("MB") => (&s[..s.len() - 2], 1 << 20),
+			_ if s.ends_with("KB") => (&s[..s.len() - 2], 1 << 10),
+			_ => (s.as_str(), 1),
+		};
+
+		match num.trim().parse::<u64>() {
+			Ok(num) if num > 0 => Ok(Rotation::Size(num * mult)),
+			_ => Err(format!("Invalid log rotation \"{}\"", s)),
+		}
+	}
+}
+
+/// A log file that's rotated daily or once it reaches a size. Old files are renamed to `mc.log.1`,
+/// `mc.log.2` and so on, keeping at most `keep` of them.
+struct RotatingFile {
+	path: PathBuf,
+	rotation: Rotation,
+	keep: usize,
+	file: File,
+	size: u64,
+	opened: NaiveDate,
+	/// Only rotate between lines, since fern writes a line in several pieces.
+	at_line_start: bool,
+}
+
+impl RotatingFile {
+	fn open(path: &Path, rotation: Rotation, keep: usize) -> io::Result<Self> {
+		if let Some(dir) = path.parent().filter(|x| !x.as_os_str().is_empty()) {
+			fs::create_dir_all(dir)?;
+		}
+
+		let file = OpenOptions::new().create(true).append(true).open(path)?;
+		let size = file.metadata()?.len();
+
+		Ok(Self {
+			path: path.to_path_buf(),
+			rotation,
+			keep,
+			file,
+			size,
+			opened: Local::now().date_naive(),
+			at_line_start: true,
+		})
+	}
+
+	fn due(&self, len: usize) -> bool {
+		if !self.at_line_start {
+			return false;
+		}
+
+		match self.rotation {
+			Rotation::Never => false,
+			Rotation::Daily => Local::now().date_naive() != self.opened,
+			Rotation::Size(max) => self.size > 0 && self.size + len as u64 > max,
+		}
+	}
+
+	fn numbered(&self, n: usize) -> PathBuf {
+		let mut path = self.path.clone().into_os_string();
+		path.push(format!(".{}", n));
+		path.into()
+	}
+
+	fn rotate(&mut self) -> io::Result<()> {
+		self.file.flush()?;
+
+		if self.keep == 0 {
+			fs::remove_file(&self.path)?;
+		} else {
+			// The oldest file is overwritten by the rename below it.
+			for n in (1..self.keep).rev() {
+				let from = self.numbered(n);
+				if from.exists() {
+					fs::rename(from, self.numbered(n + 1))?;
+				}
+			}
+			fs::rename(&self.path, self.numbered(1))?;
+		}
+
+		*self = Self::open(&self.path, self.rotation, self.keep)?;
+		Ok(())
+	}
+}
+
+impl Write for RotatingFile {
+	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+		if self.due(buf.len()) {
+			self.rotate()?;
+		}
+
+		let n = self.file.write(buf)?;
+		self.size += n as u64;
+		if n > 0 {
+			self.at_line_start = buf[n - 1] == b'\n';
+		}
+
+		Ok(n)
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		self.file.flush()
+	}
+}
+
+fn json_line(message: &std::fmt::Arguments, record: &log::Record) -> String {
+	let mut line = Map::new();
+	line.insert("time".into(), Local::now().to_rfc3339().into());
+	line.insert("level".into(), record.level().as_str().into());
+	line.insert("target".into(), record.target().into());
+	line.insert("message".into(), message.to_string().into());
+
+	let context = context();
+	for (key, val) in [("session", context.session), ("user", context.user), ("guild", context.guild)] {
+		if let Some(val) = val {
+			line.insert(key.into(), val.into());
+		}
+	}
+
+	Value::Object(line).to_string()
+}
+
+/// Set up logging to stdout and a file, configured by these environment variables:
+///
+/// - `LOG_LEVEL`: our own log level (default `trace`)
+/// - `LOG_DEPS_LEVEL`: the log level for dependencies (default `warn`)
+/// - `LOG_FORMAT`: `text` (default, colored on stdout) or `json` (one object per line)
+/// - `LOG_PATH`: the log file (default `mc.log`); set it empty to only log to stdout
+/// - `LOG_ROTATE`: `never` (default), `daily`, or a size like `10MB`
+/// - `LOG_KEEP`: how many rotated files to keep (default 5)
+pub fn setup() {
+	let level = |var: &str, default: LevelFilter| {
+		env::var(var)
+			.map(|x| x.parse().unwrap_or_else(|_| panic!("{} is not a valid log level", var)))
+			.unwrap_or(default)
+	};
+	let level_ours = level("LOG_LEVEL", LevelFilter::Trace);
+	let level_deps = level("LOG_DEPS_LEVEL", LevelFilter::Warn);
+
+	let json = match env::var("LOG_FORMAT").unwrap_or_default().to_lowercase().as_str() {
+		"" | "text" => false,
+		"json" => true,
+		other => panic!("LOG_FORMAT must be text or json, not {}", other),
+	};
+
+	let path = env::var("LOG_PATH").unwrap_or_else(|_| "mc.log".to_string());
+	let rotation: Rotation = env::var("LOG_ROTATE").unwrap_or_default().parse().unwrap();
+	let keep: usize = env::var("LOG_KEEP")
+		.map(|x| x.parse().expect("LOG_KEEP is not a valid number"))
+		.unwrap_or(5);
+
+	let colors_line = ColoredLevelConfig::new()
+		.error(Color::BrightRed)
+		.warn(Color::BrightYellow)
+		.info(Color::BrightWhite)
+		.debug(Color::White)
+		.trace(Color::BrightBlack);
+
+	let colors_level = colors_line
+		.error(Color::Red)
+		.warn(Color::Yellow)
+		.info(Color::BrightGreen)
+		.debug(Color::BrightCyan)
+		.trace(Color::Black);
+
+	let stdout = fern::Dispatch::new()
+		.format(move |out, message, record| {
+			if json {
+				return out.finish(format_args!("{}", json_line(message, record)));
+			}
+
+			out.finish(format_args!(
+				"{color_line}[{date}][{target}][{level}{color_line}] {message}\x1B[0m",
+				color_line = format_args!(
+					"\x1B[{}m",
+					colors_line.get_color(&record.level()).to_fg_str()
+				),
+				date = Local::now().format("%Y-%m-%d %H:%M:%S"),
+				target = record.target(),
+				level = colors_level.color(record.level()),
+				message = message,
+			));
+		})
+		.chain(io::stdout());
+
+	let mut dispatch = fern::Dispatch::new()
+		.level(level_deps)
+		.level_for("missioncontrol", level_ours)
+		.chain(stdout);
+
+	if !path.is_empty() {
+		let file = RotatingFile::open(Path::new(&path), rotation, keep).expect("Error opening log file");
+
+		// Colors only make sense on a terminal.
+		dispatch = dispatch.chain(fern::Dispatch::new()
+			.format(move |out, message, record| {
+				if json {
+					return out.finish(format_args!("{}", json_line(message, record)));
+				}
+
+				out.finish(format_args!(
+					"[{date}][{target}][{level}] {message}",
+					date = Local::now().format("%Y-%m-%d %H:%M:%S"),
+					target = record.target(),
+					level = record.level(),
+					message = message,
+				));
+			})
+			.chain(Box::new(file) as Box<dyn Write + Send>));
+	}
+
+	dispatch.apply().unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn rotation_parsing() {
+		assert!(matches!("".parse(), Ok(Rotation::Never)));
+		assert!(matches!("Daily".parse(), Ok(Rotation::Daily)));
+		assert!(matches!("10MB".parse(), Ok(Rotation::Size(10485760))));
+		assert!(matches!(" 500kb ".parse(), Ok(Rotation::Size(512000))));
+		assert!(matches!("1048576".parse(), Ok(Rotation::Size(1048576))));
+		assert!("0MB".parse::<Rotation>().is_err());
+		assert!("weekly".parse::<Rotation>().is_err());
+	}
+
+	#[test]
+	fn size_rotation_keeps_whole_lines() {
+		let dir = env::temp_dir().join(format!("mc-log-test-{}", std::process::id()));
+		let path = dir.join("mc.log");
+		let _ = fs::remove_dir_all(&dir);
+		let mut file = RotatingFile::open(&path, Rotation::Size(10), 2).unwrap();
+
+		// A line written in pieces is never split across files.
+		for piece in ["line one", "\n", "two\n", "three\n", "four\n"] {
+			file.write_all(piece.as_bytes()).unwrap();
+		}
+
+		assert_eq!(fs::read_to_string(&path).unwrap(), "four\n");
+		assert_eq!(fs::read_to_string(file.numbered(1)).unwrap(), "two\nthree\n");
+		assert_eq!(fs::read_to_string(file.numbered(2)).unwrap(), "line one\n");
+		assert!(!file.numbered(3).exists());
+
+		fs::remove_dir_all(dir).unwrap();
+	}
+}
\ No newline at end of file
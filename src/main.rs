@@ -2,19 +2,29 @@
 extern crate log;
 
 use std::env;
+use std::sync::{Arc, Mutex};
 
-use fern::colors::{Color, ColoredLevelConfig};
 use serenity::Client;
 use serenity::prelude::GatewayIntents;
 
 
 mod bot;
+mod cli;
+mod logging;
 
 #[tokio::main]
 async fn main() {
 	// Load the .env file to populate BOT_TOKEN and APP_ID
 	dotenv::dotenv().ok();
 
+	// Any arguments mean we're running a one-off admin task instead of the bot. Those set up what
+	// they need themselves, so `healthcheck` works without a token and never touches the log file.
+	let args: Vec<String> = env::args().skip(1).collect();
+	if !args.is_empty() {
+		cli::run(&args).await;
+		return;
+	}
+
 	let token = env::var("BOT_TOKEN").expect("Expected a token in the environment");
 
 	let application_id: u64 = env::var("APP_ID")
@@ -22,58 +32,76 @@ async fn main() {
 		.parse()
 		.expect("application id is not a valid id");
 
-	setup_logger();
+	logging::setup();
+
+	for guild in bot::config::GUILDS {
+		for missing in guild.missing_targets() {
+			warn!("Guild {}: {}", guild.id, missing);
+		}
+	}
 
-	let bot = bot::Bot;
+	let bot = Arc::new(bot::Bot::new());
+
+	let db = Arc::new(Mutex::new(bot::db::open()));
 
 	let mut client = Client::builder(token, GatewayIntents::all())
-		.event_handler(bot)
+		.event_handler_arc(bot.clone())
 		.application_id(application_id)
 		.await
 		.expect("Error creating client");
 
+	client.data.write().await.insert::<bot::db::Database>(db.clone());
+
+	// On SIGTERM (from Docker) or Ctrl-C, close everything down before the gateway goes away.
+	let shard_manager = client.shard_manager.clone();
+	let handler = bot.clone();
+	tokio::spawn(async move {
+		shutdown_signal().await;
+		info!("Shutting down...");
+
+		handler.shutdown().await;
+		shard_manager.lock().await.shutdown_all().await;
+	});
+
+	tokio::spawn(bot::http::serve(bot::http::HttpState {
+		bot: bot.clone(),
+		shard_manager: client.shard_manager.clone(),
+		db,
+	}));
+
 	info!("Initializing Mission Control...");
 
 	if let Err(why) = client.start().await {
 		error!("Client error: {:?}", why);
 	}
 
+	// This is a no-op if we got here through a signal, but the client can also stop on its own.
+	bot.scheduler.shutdown().await;
+
+	// Make sure the audit log and everything else we wrote is on disk.
+	if let Some(db) = client.data.read().await.get::<bot::db::Database>() {
+		if db.lock().unwrap().cache_flush().is_err() {
+			error!("Error flushing the database");
+		}
+	}
+
 	info!("Goodbye!");
+	log::logger().flush();
 }
 
-fn setup_logger() {
-	let colors_line = ColoredLevelConfig::new()
-		.error(Color::BrightRed)
-		.warn(Color::BrightYellow)
-		.info(Color::BrightWhite)
-		.debug(Color::White)
-		.trace(Color::BrightBlack);
-
-	let colors_level = colors_line.clone()
-		.error(Color::Red)
-		.warn(Color::Yellow)
-		.info(Color::BrightGreen)
-		.debug(Color::BrightCyan)
-		.trace(Color::Black);
-
-	fern::Dispatch::new()
-		.format(move |out, message, record| {
-			out.finish(format_args!(
-				"{color_line}[{date}][{target}][{level}{color_line}] {message}\x1B[0m",
-				color_line = format_args!(
-					"\x1B[{}m",
-					colors_line.get_color(&record.level()).to_fg_str()
-				),
-				date = chrono::Local::now().format("%Y-%m-%d %H:%M:%S"),
-				target = record.target(),
-				level = colors_level.color(record.level()),
-				message = message,
-			));
-		})
-		.level(log::LevelFilter::Warn)
-		.level_for("missioncontrol", log::LevelFilter::Trace)
-		.chain(std::io::stdout())
-		.chain(fern::log_file("mc.log").unwrap())
-		.apply()
-		.unwrap();
+/// Wait until we're asked to stop.
+async fn shutdown_signal() {
+	#[cfg(unix)]
+	{
+		let mut term = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+			.expect("Error installing SIGTERM handler");
+
+		tokio::select! {
+			_ = tokio::signal::ctrl_c() => {}
+			_ = term.recv() => {}
+		}
+	}
+
+	#[cfg(not(unix))]
+	tokio::signal::ctrl_c().await.ok();
 }
\ No newline at end of file